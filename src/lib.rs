@@ -1,8 +1,14 @@
 use std::fs;
+use std::io::{self, Write};
 use std::collections::hash_map;
+use std::collections::BTreeMap;
 use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, OnceLock};
 
-#[derive(Debug, Clone)]
+pub mod prefix;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Keyword {
     Let,
     Global,
@@ -10,16 +16,114 @@ pub enum Keyword {
     PrintLn,
     Fn,
     For,
-    If
+    If,
+    Defined,
+    Undef,
+    Loop,
+    Break,
+    Exit,
+    PrintJoined,
+    Eval,
+    Times,
+    Capture,
+    Def,
+    SwapVars,
+    Rename,
+    PrintN,
+    PrintStack,
+    ForCollect,
+    Defer,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Op {
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    Min,
+    Max,
+    Abs,
+    Reverse,
+    Equal,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+    Copy,
+    IsNone,
+    OrElse,
+    ReadFile,
+    WriteFile,
+    Sum,
+    Product,
+    Count,
+    MinBy,
+    MaxBy,
+    Enumerate,
+    Range,
+    RangeStep,
+    ConcatAll,
+    Take,
+    Skip,
+    First,
+    Last,
+    Flatten,
+    Random,
+    Now,
+    Rot,
+    RRot,
+    Tuck,
+    Dup2,
+    Pack,
+    Unpack,
+    CallDepth,
+    Chars,
+    Bytes,
+    AssertStack,
+    Splice,
+    Partition,
+    Upper,
+    Lower,
+    Trim,
+    Builtins,
+    AMin,
+    AMax,
+    ToInt,
+    ToBool,
+    Sort,
+    SortDesc,
+    Uniq,
+    DedupAll,
+    Scan,
+    Hex,
+    Bin,
+    ReadBytes,
+    ToBytes,
+    FromBytes,
+    IsEmpty,
+    ToArray,
+    PadLeft,
+    PadRight,
+    Clamp,
+    Any,
+    All,
+    SetAll,
+    Join,
+    Nth,
+    IsDigit,
+    IsAlpha,
+    IsAlnum,
+    IsSpace,
+    Gcd,
+    Lcm,
+    ModPow,
+    StartsWith,
+    EndsWith,
+    Contains,
+    /// only exists between lexing a backtick and `quote_ops`
+    Quote,
     Assign,
     AddAssign,
     SubAssign,
@@ -36,13 +140,18 @@ pub enum Op {
     IndexArray,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Fn {
     args: Vec<String>,
-    body: Vec<Value>,
+    /// shared, so passing a fn around or calling it doesnt copy the whole body
+    body: Arc<[Value]>,
+    /// vars captured when the fn was made, empty for plain fns
+    env: BTreeMap<String, Value>,
+    /// set by def, so the body can call itself by this name
+    name: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Value {
     Int(i32),
     Char(char),
@@ -54,8 +163,105 @@ pub enum Value {
     Fn(Fn),
     Tuple(Vec<Value>),
     Block(Vec<Value>),
-    Array(Vec<Value>),
-    None
+    /// shared between copies, writing to one copies it first so each still acts like its own value
+    Array(Arc<Vec<Value>>),
+    /// raw binary data, for files that arent utf-8
+    Bytes(Vec<u8>),
+    /// what `range` gives, only turned into an array when something other than `for` needs one
+    Range { start: i32, end: i32, step: i32 },
+    None,
+    /// what `let`/`global` bind until the first `=`, reading it is an error
+    Unset,
+}
+
+impl Value {
+    /// functions (and anything holding one) can't be used as keys
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            Value::Fn(_) | Value::ExtFn(_) => false,
+            Value::Tuple(vs) | Value::Block(vs) => vs.iter().all(|v| v.is_hashable()),
+            Value::Array(vs) => vs.iter().all(|v| v.is_hashable()),
+            _ => true,
+        }
+    }
+}
+
+/// structural equality, fns are never equal to anything
+pub fn deep_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Fn(_), _) | (_, Value::Fn(_)) => false,
+        (Value::Tuple(x), Value::Tuple(y)) | (Value::Block(x), Value::Block(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(a, b)| deep_eq(a, b))
+        }
+        (Value::Array(x), Value::Array(y)) => {
+            // the same array shared by two copies is equal without looking inside
+            Arc::ptr_eq(x, y) || x.len() == y.len() && x.iter().zip(y.iter()).all(|(a, b)| deep_eq(a, b))
+        }
+        _ => a == b,
+    }
+}
+
+/// the ints a range goes through, the end is never included
+fn range_iter(start: i32, end: i32, step: i32) -> impl Iterator<Item = i32> {
+    std::iter::successors(Some(start), move |i| i.checked_add(step))
+        .take_while(move |&i| (step > 0 && i < end) || (step < 0 && i > end))
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// turns a lazy range into a real array, anything else is returned as is
+fn to_array(v: Value) -> Value {
+    if let Value::Range { start, end, step } = v {
+        Value::Array(Arc::new(range_iter(start, end, step).map(Value::Int).collect()))
+    } else {
+        v
+    }
+}
+
+/// ordering between values of the same kind, None when they cant be compared
+pub fn compare(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        (Value::Char(x), Value::Char(y)) => Some(x.cmp(y)),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        // lexicographic, the first field that differs decides and a shorter prefix comes first
+        (Value::Tuple(x), Value::Tuple(y)) => {
+            for (a, b) in x.iter().zip(y) {
+                match compare(a, b)? {
+                    std::cmp::Ordering::Equal => {}
+                    o => return Some(o),
+                }
+            }
+            Some(x.len().cmp(&y.len()))
+        }
+        _ => None,
+    }
+}
+
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Value::Int(i) => i.hash(state),
+            Value::Char(c) => c.hash(state),
+            Value::String(s) | Value::Ident(s) => s.hash(state),
+            Value::Operation(op) => op.hash(state),
+            Value::Keyword(kw) => kw.hash(state),
+            Value::Tuple(vs) | Value::Block(vs) => vs.hash(state),
+            Value::Array(vs) => vs.hash(state),
+            Value::Bytes(b) => b.hash(state),
+            Value::Range { start, end, step } => (start, end, step).hash(state),
+            Value::None | Value::Unset => {}
+            Value::Fn(_) | Value::ExtFn(_) => {
+                panic!("cant use a fn as a key: {}", self);
+            }
+        }
+    }
 }
 
 // type TypeRef = usize;
@@ -94,9 +300,22 @@ impl Display for Value {
             Value::String(s) => {
                 write!(f, "{}", s)
             }
+            Value::Bytes(b) => {
+                write!(f, "(bytes:").unwrap();
+                for byte in b {
+                    write!(f, " {:02x}", byte).unwrap();
+                }
+                write!(f, ")")
+            }
+            Value::Range { .. } => {
+                write!(f, "{}", to_array(self.clone()))
+            }
             Value::None => {
                 write!(f, "none")
             }
+            Value::Unset => {
+                write!(f, "unset")
+            }
             Value::Operation(op) => {
                 write!(f, "(op: {:?})", op)
             }
@@ -120,7 +339,7 @@ impl Display for Value {
                     if i != b.len() - 1 {
                         write!(f, " ").unwrap();
                     } else {
-                        write!(f, "\n").unwrap();
+                        writeln!(f).unwrap();
                     }
                 }
                 write!(f, "}}")
@@ -132,7 +351,7 @@ impl Display for Value {
                     if i != b.len() - 1 {
                         write!(f, " ").unwrap();
                     } else {
-                        write!(f, "\n").unwrap();
+                        writeln!(f).unwrap();
                     }
                 }
                 write!(f, "]")
@@ -141,6 +360,17 @@ impl Display for Value {
     }
 }
 
+/// how a call to `run` finished
+#[derive(Debug, Clone, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break,
+    /// stops everything, the host decides what to do with the code
+    Exit(i32),
+    /// the time given to set_timeout ran out, stops everything like exit
+    Timeout,
+}
+
 #[derive(Debug)]
 pub enum Delim {
     Tuple(Vec<Value>),
@@ -148,31 +378,215 @@ pub enum Delim {
     Array(Vec<Value>)
 }
 
+/// whether a closing delimiter belongs to a nested start already collected in `vs`
+fn is_nested(vs: &[Value], start: Op, end: Op) -> bool {
+    let mut depth = 0;
+    for v in vs {
+        if let Value::Operation(op) = v {
+            if *op == start {
+                depth += 1;
+            } else if *op == end {
+                depth -= 1;
+            }
+        }
+    }
+    depth > 0
+}
+
+/// counts collected while `profiling` is on
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExecStats {
+    pub values: u64,
+    pub calls: u64,
+}
+
+/// where print and println write to
+pub struct Output<'a>(pub Box<dyn Write + Send + 'a>);
+
+impl std::fmt::Debug for Output<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Output")
+    }
+}
+
+// a program can be tokenized once and run on several threads, each with its own state
+const _: fn() = || {
+    fn send<T: Send>() {}
+    fn sync<T: Sync>() {}
+    send::<InterpreterState>();
+    send::<Program>();
+    sync::<Program>();
+    sync::<ExtFns>();
+    sync::<MultiExtFns>();
+};
+
+pub type ExtFns = hash_map::HashMap<String, fn(Value) -> Value>;
+/// ext fns that can give back several values, each one is pushed in order
+pub type MultiExtFns = hash_map::HashMap<String, fn(Value) -> Vec<Value>>;
+
+/// builds an ext fn table in one go instead of inserting one at a time
+pub fn with_ext_fns<S: Into<String>>(pairs: impl IntoIterator<Item = (S, fn(Value) -> Value)>) -> ExtFns {
+    pairs.into_iter()
+        .map(|(name, f)| (name.into(), f))
+        .collect()
+}
+
+/// how many scopes (fn calls, loop and if bodies, ...) can be nested before giving up
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// how much stack nested scopes can use before giving up, whichever limit comes first wins.
+/// a scope takes about 5kb in release builds but about 60kb in debug builds, so the depth alone
+/// isnt enough, and this leaves half of the 2mb a spawned thread gets for everything else
+pub const DEFAULT_STACK_LIMIT: usize = 1024 * 1024;
+
+/// roughly where the stack is right now
+#[inline(never)]
+fn stack_addr() -> usize {
+    let here = 0u8;
+    std::hint::black_box(&here) as *const u8 as usize
+}
+
+/// looking at the clock on every value would cost more than running most of them
+const TIMEOUT_CHECK_EVERY: u32 = 1024;
+
+fn seed_from_time() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    nanos | 1
+}
+
 #[derive(Debug)]
 pub struct InterpreterState<'a> {
     pub stack: Vec<Value>,
-    pub vars: hash_map::HashMap<String, Value>,
-    pub globals: hash_map::HashMap<String, Value>,
+    pub vars: BTreeMap<String, Value>,
+    pub globals: BTreeMap<String, Value>,
     pub delims: Vec<Delim>,
-    pub ext_fns: &'a hash_map::HashMap<String, fn(Value) -> Value>
+    pub ext_fns: &'a ExtFns,
+    pub multi_ext_fns: Option<&'a MultiExtFns>,
+    pub profiling: bool,
+    /// makes run panic if anything is left on the stack at the end
+    pub strict: bool,
+    stats: ExecStats,
+    pub out: Output<'a>,
+    rng: u64,
+    started: std::time::Instant,
+    depth: usize,
+    max_depth: usize,
+    /// where the stack was when the outermost run started
+    stack_base: usize,
+    stack_limit: usize,
+    /// blocks from defer, run last to first when this run ends
+    deferred: Vec<Vec<Value>>,
+    /// vars renamed in this scope, so write_back can rename them in the scope they came from too
+    renamed: Vec<(String, String)>,
+    deadline: Option<std::time::Instant>,
+    /// values run so far, shared with child scopes so the deadline is checked every so often
+    ticks: u32,
 }
 
 impl<'a> InterpreterState<'a> {
+    pub fn new(ext_fns: &'a ExtFns) -> Self {
+        InterpreterState::with_output(ext_fns, Box::new(io::stdout()))
+    }
+    pub fn with_output(ext_fns: &'a ExtFns, out: Box<dyn Write + Send + 'a>) -> Self {
+        InterpreterState {
+            stack: Vec::new(),
+            vars: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            delims: Vec::new(),
+            ext_fns,
+            multi_ext_fns: None,
+            profiling: false,
+            strict: false,
+            stats: ExecStats::default(),
+            out: Output(out),
+            rng: seed_from_time(),
+            started: std::time::Instant::now(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            stack_base: 0,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            deferred: Vec::new(),
+            renamed: Vec::new(),
+            deadline: None,
+            ticks: 0,
+        }
+    }
+    /// makes `random` give the same numbers every run
+    pub fn seed(&mut self, seed: u64) {
+        // xorshift gets stuck on 0
+        self.rng = if seed == 0 { 0x9e3779b97f4a7c15 } else { seed };
+    }
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+    /// untrusted scripts can be given a tighter limit than DEFAULT_MAX_DEPTH
+    pub fn set_recursion_limit(&mut self, n: usize) {
+        self.max_depth = n;
+    }
+    pub fn recursion_limit(&self) -> usize {
+        self.max_depth
+    }
+    /// hosts running on a thread with a bigger stack can allow more than DEFAULT_STACK_LIMIT bytes
+    pub fn set_stack_limit(&mut self, bytes: usize) {
+        self.stack_limit = bytes;
+    }
+    /// makes run give up with Flow::Timeout once this much time has passed from now,
+    /// for untrusted scripts that might loop forever
+    pub fn set_timeout(&mut self, limit: std::time::Duration) {
+        self.deadline = Some(std::time::Instant::now() + limit);
+    }
+    fn out_of_time(&mut self) -> bool {
+        let Some(deadline) = self.deadline else {
+            return false;
+        };
+        self.ticks = self.ticks.wrapping_add(1);
+        self.ticks.is_multiple_of(TIMEOUT_CHECK_EVERY) && std::time::Instant::now() >= deadline
+    }
+    pub fn stats(&self) -> ExecStats {
+        self.stats.clone()
+    }
+    /// looks up a global after a script has run
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+    /// all globals, in name order
+    pub fn globals(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.globals.iter()
+    }
+    /// seeds globals, runs, and hands back every global afterwards (along with how the run ended),
+    /// for using a script as a transformation over some named data
+    pub fn run_with_globals(&mut self, vals: &[Value], globals: hash_map::HashMap<String, Value>) -> (hash_map::HashMap<String, Value>, Flow) {
+        self.globals.extend(globals);
+        let flow = self.run(vals);
+        let globals = self.globals.iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        (globals, flow)
+    }
     fn get_int(&mut self) -> Option<i32> {
         let val = self.stack.pop().unwrap();
+        self.as_int(val)
+    }
+    fn as_int(&mut self, val: Value) -> Option<i32> {
         match val {
             Value::Int(i) => {
-                return Some(i);
+                Some(i)
             }
             Value::Ident(ref i) => {
-                if let Some(Value::Int(v)) = self.get_var(i) {
-                    return Some(*v);
+                if let Some(Value::Int(v)) = self.read_var(i) {
+                    Some(*v)
                 } else {
-                    return None;
+                    None
                 }
             }
             _ => {
-                return None;
+                None
             }
         }
     }
@@ -184,21 +598,32 @@ impl<'a> InterpreterState<'a> {
     }
     fn get_value(&mut self) -> Option<Value> {
         let v = self.pop_value();
-        if let Some(Value::Ident(ref i)) = v {
-            let r = self.get_var(i);
-            if r.is_some() {
-                return r.cloned();
-            } else if self.ext_fns.contains_key(i) {
-                return Some(Value::ExtFn(i.to_string()));
+        v.map(|v| to_array(self.resolve(v)))
+    }
+    /// turns an ident into the value it names, anything else is returned as is.
+    /// vars come first, then globals, then ext fns, so user bindings shadow ext fns
+    fn resolve(&mut self, v: Value) -> Value {
+        if let Value::Ident(ref i) = v {
+            if let Some(r) = self.read_var(i) {
+                return r.clone();
+            } else if self.has_ext_fn(i) {
+                return Value::ExtFn(i.to_string());
             }
         }
-        return v;
+        v
+    }
+    /// like resolve, but the names inside a tuple too. arrays are resolved when they are made
+    fn resolve_deep(&mut self, v: Value) -> Value {
+        match v {
+            Value::Tuple(vs) => Value::Tuple(vs.into_iter().map(|v| self.resolve_deep(v)).collect()),
+            v => self.resolve(v),
+        }
     }
     fn add_global(&mut self, name: &str) {
-        self.globals.insert(name.to_string(), Value::None);
+        self.globals.insert(name.to_string(), Value::Unset);
     }
     fn add_var(&mut self, name: &str) {
-        self.vars.insert(name.to_string(), Value::None);
+        self.vars.insert(name.to_string(), Value::Unset);
     }
     fn set_var(&mut self, name: &str, val: Value) {
         let chud = self.vars.get_mut(name)
@@ -206,52 +631,292 @@ impl<'a> InterpreterState<'a> {
             .unwrap();
         *chud = val;
     }
+    fn remove_var(&mut self, name: &str) -> Option<Value> {
+        self.vars.remove(name)
+            .or_else(|| self.globals.remove(name))
+    }
+    /// like get_var, but a var that was declared and never set is an error
+    fn read_var(&mut self, name: &str) -> Option<&Value> {
+        match self.get_var(name) {
+            Some(Value::Unset) => panic!("{} is used before it was set", name),
+            v => v,
+        }
+    }
     fn get_var(&mut self, name: &str) -> Option<&Value> {
         self.vars.get(name)
             .or(self.globals.get(name))
     }
-    fn eval_tuple(&mut self, tuple: Value) -> Value {
+    /// a fresh scope for running a block or fn, sharing globals with this one
+    /// the child borrows the globals and output until it is absorbed again
+    fn child(&mut self, vars: BTreeMap<String, Value>) -> InterpreterState<'a> {
+        if self.depth >= self.max_depth {
+            panic!("recursion limit of {} reached", self.max_depth);
+        }
+        if stack_addr().abs_diff(self.stack_base) > self.stack_limit {
+            panic!("recursion limit reached after {} scopes, they used up the {} bytes of stack allowed", self.depth, self.stack_limit);
+        }
+        let out = std::mem::replace(&mut self.out.0, Box::new(io::sink()));
+        InterpreterState {
+            vars,
+            // moved instead of copied, nothing here looks at them while the child runs
+            globals: std::mem::take(&mut self.globals),
+            profiling: self.profiling,
+            rng: self.rng,
+            started: self.started,
+            depth: self.depth + 1,
+            max_depth: self.max_depth,
+            stack_base: self.stack_base,
+            stack_limit: self.stack_limit,
+            multi_ext_fns: self.multi_ext_fns,
+            deadline: self.deadline,
+            ticks: self.ticks,
+            ..InterpreterState::with_output(self.ext_fns, out)
+        }
+    }
+    /// takes back the globals, output (and stats) from a finished child scope
+    fn absorb(&mut self, child: &mut InterpreterState<'a>) {
+        self.globals = std::mem::take(&mut child.globals);
+        std::mem::swap(&mut self.out, &mut child.out);
+        self.rng = child.rng;
+        self.ticks = child.ticks;
+        self.stats.values += child.stats.values;
+        self.stats.calls += child.stats.calls;
+    }
+    /// copies vars back out of a child scope, dropping any the child removed
+    fn write_back(&mut self, child: &InterpreterState) {
+        for (old, new) in &child.renamed {
+            if let Some(v) = self.vars.remove(old) {
+                self.vars.insert(new.clone(), v);
+                // and on up, if this scope is a child too
+                if self.depth > 0 {
+                    self.renamed.push((old.clone(), new.clone()));
+                }
+            }
+        }
+        self.vars.retain(|k, _| child.vars.contains_key(k));
+        for (k, v) in self.vars.iter_mut() {
+            if let Some(new) = child.vars.get(k) {
+                *v = new.clone();
+            }
+        }
+    }
+    /// runs a block in a child scope that sees (and can change) the current vars
+    fn run_block(&mut self, b: &[Value]) -> Flow {
+        let mut istate_new = self.child(self.vars.clone());
+        let flow = istate_new.run(b);
+        self.write_back(&istate_new);
+        self.absorb(&mut istate_new);
+        flow
+    }
+    /// runs `f` with `args` bound in order, returning whatever it left on its stack
+    fn call_fn(&mut self, f: &Fn, args: Vec<Value>) -> (Vec<Value>, Flow) {
+        let mut istate_new = self.child(f.env.clone());
+        if self.profiling {
+            self.stats.calls += 1;
+        }
+        // bound before the args, so an arg with the same name still wins
+        if let Some(name) = &f.name {
+            istate_new.add_var(name);
+            istate_new.set_var(name, Value::Fn(f.clone()));
+        }
+        for (arg, val) in f.args.iter().zip(args) {
+            istate_new.add_var(arg);
+            istate_new.set_var(arg, val);
+        }
+        let flow = istate_new.run(&f.body);
+        // idents left behind only mean something inside the fn
+        let stack = std::mem::take(&mut istate_new.stack);
+        let stack = stack.into_iter().map(|v| istate_new.resolve_deep(v)).collect();
+        self.absorb(&mut istate_new);
+        (stack, flow)
+    }
+    /// registers a second table of ext fns next to the one given to new
+    pub fn set_multi_ext_fns(&mut self, fns: &'a MultiExtFns) {
+        self.multi_ext_fns = Some(fns);
+    }
+    fn has_ext_fn(&self, name: &str) -> bool {
+        self.ext_fns.contains_key(name)
+            || self.multi_ext_fns.is_some_and(|m| m.contains_key(name))
+    }
+    /// whether a var, global or ext fn has this name, any of them hides a word op
+    fn is_bound(&mut self, name: &str) -> bool {
+        self.get_var(name).is_some() || self.has_ext_fn(name)
+    }
+    /// calls an ext fn from either table and gives back everything it returned
+    fn call_ext_fn_multi(&mut self, name: &str, arg: Value) -> Vec<Value> {
+        if self.profiling {
+            self.stats.calls += 1;
+        }
+        match self.ext_fns.get(name) {
+            Some(f) => vec![f(arg)],
+            None => self.multi_ext_fns.unwrap()[name](arg),
+        }
+    }
+    /// like call_ext_fn_multi but keeps only the top (last) result, none if there wasnt one
+    fn call_ext_fn(&mut self, name: &str, arg: Value) -> Value {
+        self.call_ext_fn_multi(name, arg).pop().unwrap_or(Value::None)
+    }
+    /// int arithmetic, applied element by element when either side is an array
+    /// (a plain int on the other side is used for every element)
+    fn arith(&mut self, op: &Op, a: Value, b: Value) -> Value {
+        match (a, b) {
+            (Value::Array(x), Value::Array(y)) => {
+                if x.len() != y.len() {
+                    panic!("cant {} arrays of {} and {} elements", op_name(op), x.len(), y.len());
+                }
+                let (x, y) = (Arc::unwrap_or_clone(x), Arc::unwrap_or_clone(y));
+                Value::Array(Arc::new(x.into_iter().zip(y).map(|(a, b)| self.arith(op, a, b)).collect()))
+            }
+            (Value::Array(x), b) => {
+                let x = Arc::unwrap_or_clone(x);
+                Value::Array(Arc::new(x.into_iter().map(|a| self.arith(op, a, b.clone())).collect()))
+            }
+            (a, Value::Array(y)) => {
+                let y = Arc::unwrap_or_clone(y);
+                Value::Array(Arc::new(y.into_iter().map(|b| self.arith(op, a.clone(), b)).collect()))
+            }
+            (a, b) => {
+                let (Some(x), Some(y)) = (self.as_int(a.clone()), self.as_int(b.clone())) else {
+                    panic!("cant {} {} and {}", op_name(op), a, b);
+                };
+                Value::Int(match op {
+                    Op::Add => x + y,
+                    Op::Sub => x - y,
+                    Op::Mul => x * y,
+                    Op::Div => x / y,
+                    Op::Mod => x % y,
+                    Op::Min => x.min(y),
+                    _ => x.max(y),
+                })
+            }
+        }
+    }
+    /// prints values on one line, space separated, in the order they were pushed
+    fn print_all(&mut self, vs: Vec<Value>) -> Flow {
+        let mut strs = Vec::with_capacity(vs.len());
+        for v in vs {
+            let v = to_array(self.resolve(v));
+            let (v, flow) = self.eval_tuple(v);
+            if flow != Flow::Normal {
+                return flow;
+            }
+            strs.push(v.to_string());
+        }
+        writeln!(self.out.0, "{}", strs.join(" ")).unwrap();
+        Flow::Normal
+    }
+    /// pops the ( args ) tuple and { body } block a fn or def is made from
+    fn pop_fn(&mut self, kw: &str) -> Fn {
+        let block = match self.get_value() {
+            Some(Value::Block(b)) => b,
+            Some(v) => {
+                panic!("{} needs a {{ body }} block, got {}", kw, v);
+            }
+            None => {
+                panic!("{} needs a ( args ) tuple and a {{ body }} block, got nothing", kw);
+            }
+        };
+        let tuple = match self.get_value() {
+            Some(Value::Tuple(t)) => t,
+            Some(v) => {
+                panic!("{} needs a ( args ) tuple before its body, got {}", kw, v);
+            }
+            None => {
+                panic!("{} needs a ( args ) tuple before its body, got nothing", kw);
+            }
+        };
+        let mut args = vec![];
+        for arg in tuple {
+            if let Value::Ident(i) = arg {
+                args.push(i);
+            } else {
+                panic!("{} args must be idents, got {}", kw, arg);
+            }
+        }
+        Fn { args, body: block.into(), env: BTreeMap::new(), name: None }
+    }
+    /// calls a fn or ext fn with a single argument and takes its top result,
+    /// along with how the fn ended so an exit inside it isnt lost
+    fn apply(&mut self, f: &Value, arg: Value) -> (Value, Flow) {
+        match f {
+            Value::Fn(f) => {
+                let (mut ret, flow) = self.call_fn(f, vec![arg]);
+                (ret.pop().unwrap_or(Value::None), flow)
+            }
+            Value::ExtFn(name) => {
+                (self.call_ext_fn(name, arg), Flow::Normal)
+            }
+            _ => {
+                panic!("cant call non-fn {}", f);
+            }
+        }
+    }
+    /// runs the values in a tuple literal, an exit (or break) inside it is passed back with the result
+    fn eval_tuple(&mut self, tuple: Value) -> (Value, Flow) {
         if let Value::Tuple(t) = tuple {
-            let mut istate_new = InterpreterState {
-                stack: Vec::new(),
-                vars: self.vars.clone(),
-                globals: self.globals.clone(),
-                delims: Vec::new(),
-                ext_fns: self.ext_fns
-            };
-            istate_new.run(&t);
-            self.globals = istate_new.globals;
-            return Value::Tuple(istate_new.stack);
+            let (vs, flow) = self.eval_literal(&t);
+            (Value::Tuple(vs), flow)
         } else {
-            return tuple;
+            (tuple, Flow::Normal)
         }
     }
-    fn eval_array(&mut self, tuple: Value) -> Value {
+    fn eval_array(&mut self, tuple: Value) -> (Value, Flow) {
         if let Value::Array(t) = tuple {
-            let mut istate_new = InterpreterState {
-                stack: Vec::new(),
-                vars: self.vars.clone(),
-                globals: self.globals.clone(),
-                delims: Vec::new(),
-                ext_fns: self.ext_fns
-            };
-            istate_new.run(&t);
-            self.globals = istate_new.globals;
-            return Value::Array(istate_new.stack);
+            let (vs, flow) = self.eval_literal(&t);
+            (Value::Array(Arc::new(vs)), flow)
         } else {
-            return tuple;
+            (tuple, Flow::Normal)
         }
     }
-    pub fn run(&mut self, vals: &[Value]) {
-        for val in vals {
+    /// the elements are copies of what the names meant, so changing a var later doesnt change the literal
+    fn eval_literal(&mut self, vals: &[Value]) -> (Vec<Value>, Flow) {
+        let mut istate_new = self.child(self.vars.clone());
+        let flow = istate_new.run(vals);
+        let stack = std::mem::take(&mut istate_new.stack);
+        let stack = stack.into_iter().map(|v| istate_new.resolve_deep(v)).collect();
+        self.absorb(&mut istate_new);
+        (stack, flow)
+    }
+    pub fn run(&mut self, vals: &[Value]) -> Flow {
+        // child scopes keep the base of the outermost run
+        if self.depth == 0 {
+            self.stack_base = stack_addr();
+        }
+        let mut flow = self.exec(vals);
+        // deferred blocks run however the scope ended, and an exit from one of them wins
+        while let Some(b) = self.deferred.pop() {
+            let f = self.run_block(&b);
+            if let Flow::Exit(_) | Flow::Timeout = f {
+                flow = f;
+            }
+        }
+        // leftovers usually mean a missing print or an off by one somewhere
+        if self.strict && flow == Flow::Normal && !self.stack.is_empty() {
+            panic!("{} values left on the stack at the end, the top one is {}", self.stack.len(), self.stack.last().unwrap());
+        }
+        flow
+    }
+    fn exec(&mut self, vals: &[Value]) -> Flow {
+        // checked up front too, so a loop with an empty body still times out
+        if self.out_of_time() {
+            return Flow::Timeout;
+        }
+        for (i, val) in vals.iter().enumerate() {
+            if self.out_of_time() {
+                return Flow::Timeout;
+            }
+            if self.profiling {
+                self.stats.values += 1;
+            }
             if !self.delims.is_empty() {
                 match self.delims.last_mut().unwrap() {
                     Delim::Block(vs) => {
                         if let Value::Operation(Op::BlockEnd) = val {
-                            if let Delim::Block(b) = self.delims.pop().unwrap() {
+                            if is_nested(vs, Op::BlockStart, Op::BlockEnd) {
+                                vs.push(val.clone());
+                            } else if let Delim::Block(b) = self.delims.pop().unwrap() {
                                 self.push_value(Value::Block(b));
                             } else {
-                                println!("{:?}", self);
                                 panic!("cant end non-block with block end");
                             }
                         } else {
@@ -260,10 +925,11 @@ impl<'a> InterpreterState<'a> {
                     }
                     Delim::Tuple(vs) => {
                         if let Value::Operation(Op::TupleEnd) = val {
-                            if let Delim::Tuple(t) = self.delims.pop().unwrap() {
+                            if is_nested(vs, Op::TupleStart, Op::TupleEnd) {
+                                vs.push(val.clone());
+                            } else if let Delim::Tuple(t) = self.delims.pop().unwrap() {
                                 self.push_value(Value::Tuple(t));
                             } else {
-                                println!("{:?}", self);
                                 panic!("cant end non-tuple with tuple end");
                             }
                         } else {
@@ -272,12 +938,16 @@ impl<'a> InterpreterState<'a> {
                     }
                     Delim::Array(vs) => {
                         if let Value::Operation(Op::ArrayEnd) = val {
-                            if let Delim::Array(t) = self.delims.pop().unwrap() {
-                                let chud = self.eval_array(Value::Array(t));
+                            if is_nested(vs, Op::ArrayStart, Op::ArrayEnd) {
+                                vs.push(val.clone());
+                            } else if let Delim::Array(t) = self.delims.pop().unwrap() {
+                                let (chud, flow) = self.eval_array(Value::Array(Arc::new(t)));
+                                if flow != Flow::Normal {
+                                    return flow;
+                                }
                                 self.push_value(chud);
                             } else {
-                                println!("{:?}", self);
-                                panic!("cant end non-tuple with tuple end");
+                                panic!("cant end non-array with array end");
                             }
                         } else {
                             vs.push(val.clone());
@@ -286,102 +956,29 @@ impl<'a> InterpreterState<'a> {
                 }
                 continue;
             }
+            // word ops only run when nothing shadows them and the name isnt about to be bound
+            let word;
+            let val = match val {
+                Value::Ident(name) => match word_op(name) {
+                    Some(op) if !self.is_bound(name) && !binds_name(&vals[i + 1..]) => {
+                        word = Value::Operation(op.clone());
+                        &word
+                    }
+                    _ => val,
+                },
+                _ => val,
+            };
             match val {
                 Value::Operation(op) => {
-                    match op {
-                        Op::Assign => {
-                            let v = self.get_value().unwrap();
-                            if let Value::Ident(k) = self.stack.pop().unwrap() {
-                                self.set_var(&k, v.clone());
-                                // println!("set var {} to value {:?}", &k, v);
-                            } else {
-                                println!("{:?}", self);
-                                panic!("type mismatch");
-                            }
-                        }
-                        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod => {
-                            let b = self.get_int().unwrap();
-                            let a = self.get_int().unwrap();
-                            match op {
-                                Op::Add => {
-                                    self.push_value(Value::Int(a + b));
-                                }
-                                Op::Sub => {
-                                    self.push_value(Value::Int(a - b));
-                                }
-                                Op::Mul => {
-                                    self.push_value(Value::Int(a * b));
-                                }
-                                Op::Div => {
-                                    self.push_value(Value::Int(a / b));
-                                }
-                                Op::Mod => {
-                                    self.push_value(Value::Int(a % b));
-                                }
-                                _ => {}
-                            }
-                        }
-                        Op::Invert => {
-                            let a = self.get_int().unwrap();
-                            self.push_value(Value::Int(if a != 0 { 0 } else { 1 }));
-                        }
-                        Op::BlockStart => {
-                            self.delims.push(Delim::Block(Vec::new()));
-                        }
-                        Op::TupleStart => {
-                            self.delims.push(Delim::Tuple(Vec::new()));
-                        }
-                        Op::ArrayStart => {
-                            self.delims.push(Delim::Array(Vec::new()));
-                        }
-                        Op::CallFn => {
-                            match self.get_value().unwrap() {
-                                Value::Fn(f) => {
-                                    let mut istate_new = InterpreterState {
-                                        stack: Vec::new(),
-                                        vars: hash_map::HashMap::new(),
-                                        globals: self.globals.clone(),
-                                        delims: Vec::new(),
-                                        ext_fns: self.ext_fns
-                                    };
-                                    for arg in f.args.iter().rev() {
-                                        istate_new.add_var(&arg);
-                                        istate_new.set_var(&arg, self.get_value().unwrap());
-                                    }
-                                    istate_new.run(&f.body);
-                                    self.globals = istate_new.globals;
-                                }
-                                // TODO improvements needed
-                                Value::ExtFn(ref _f) => {
-                                    let f = self.ext_fns.get(_f).unwrap();
-                                    let _val = self.get_value();
-                                    let val = if _val.is_none() {
-                                        Value::None
-                                    } else {
-                                        _val.unwrap()
-                                    };
-                                    let res = f(val);
-                                    self.push_value(res);
-                                }
-                                _ => {
-                                    println!("{:?}", self);
-                                    panic!("cant call non-fn");
-                                }
-                            }
-                        }
-                        Op::IndexArray => {
-                            let index = self.get_int().unwrap();
-                            let array = self.get_value().unwrap();
-                            if let Value::Array(a) = array {
-                                self.push_value(a[index as usize].clone());
-                            } else if let Value::String(a) = array {
-                                self.push_value(Value::Char(a.as_bytes()[index as usize].into()));
-                            } else {
-                                println!("{:?}", self);
-                                panic!("index an array you tard");
-                            }
-                        }
-                        _ => {}
+                    self.check_underflow(val, op_effect(op).map_or(1, |(n, _)| n));
+                    // words that open a scope have small fns of their own, so deep recursion
+                    // doesnt carry the stack frame of every other word along at each level
+                    let flow = match op {
+                        Op::CallFn => self.exec_call(),
+                        _ => self.exec_op(op),
+                    };
+                    if flow != Flow::Normal {
+                        return flow;
                     }
                 }
                 Value::Int(_) => {
@@ -402,254 +999,2655 @@ impl<'a> InterpreterState<'a> {
                 Value::ExtFn(_) => {
                     self.push_value(val.clone());
                 }
+                Value::Bytes(_) | Value::Range { .. } => {
+                    self.push_value(val.clone());
+                }
                 Value::Keyword(ref kw) => {
-                    match kw {
-                        Keyword::Let => {
-                            if let Value::Ident(i) = self.stack.pop().unwrap() {
-                                self.add_var(&i);
-                                // println!("added var {}", &i);
-                                self.push_value(Value::Ident(i));
-                            } else {
-                                println!("{:?}", self);
-                                panic!("use let on an ident, dummy!");
-                            }
-                        }
-                        Keyword::Global => {
-                            if let Value::Ident(i) = self.stack.pop().unwrap() {
-                                self.add_global(&i);
-                                // println!("added var {}", &i);
-                                self.push_value(Value::Ident(i));
-                            } else {
-                                println!("{:?}", self);
-                                panic!("use let on an ident, dummy!");
-                            }
-                        }
-                        Keyword::Fn => {
-                            let block_ = self.get_value().unwrap();
-                            let tuple_ = self.get_value().unwrap();
-                            if let Value::Block(block) = block_ {
-                                if let Value::Tuple(tuple) = tuple_ {
-                                    let mut args = vec![];
-                                    for arg in tuple {
-                                        if let Value::Ident(i) = arg {
-                                            args.push(i);
-                                        }
-                                    }
-                                    self.push_value(Value::Fn(Fn { args, body: block }));
-                                } else {
-                                    println!("{:?}", self);
-                                    panic!("try to create a function properly next time");
-                                }
-                            } else {
-                                println!("{:?}", self);
-                                panic!("try to create a function properly next time");
-                            }
-                        }
-                        Keyword::Print => {
-                            let v = self.get_value().unwrap();
-                            print!("{}", self.eval_tuple(v));
-                        }
-                        Keyword::PrintLn => {
-                            let v = self.get_value().unwrap();
-                            println!("{}", self.eval_tuple(v));
-                        }
-                        Keyword::For => {
-                            let block = self.get_value().unwrap();
-                            let val_name = self.pop_value().unwrap();
-                            let mut array = self.get_value().unwrap();
-                            array = self.eval_array(array); // TODO remove unnecessary eval when its not a literal
-                            let mut istate_new = InterpreterState {
-                                stack: Vec::new(),
-                                vars: self.vars.to_owned(),
-                                globals: self.globals.clone(),
-                                delims: Vec::new(),
-                                ext_fns: self.ext_fns
-                            };
-                            if let Value::Array(a) = array {
-                                if let Value::Ident(ref i) = val_name {
-                                    if let Value::Block(ref b) = block {
-                                        istate_new.add_var(i);
-                                        for val in a {
-                                            istate_new.set_var(i, val);
-                                            istate_new.run(b);
-                                        }
-                                        for var in self.vars.iter_mut() {
-                                            *var.1 = istate_new.get_var(var.0).unwrap().clone();
-                                        }
-                                    } else {
-                                        println!("{:?}", self);
-                                        panic!("not a block {:?}", block);
-                                    }
-                                } else {
-                                    println!("{:?}", self);
-                                    panic!("not an ident {:?}", val_name);
-                                }
-                            } else {
-                                println!("{:?}", self);
-                                panic!("not an array {:?}", array);
-                            }
-                            self.globals = istate_new.globals;
-                        }
-                        Keyword::If => {
-                            let block = self.get_value().unwrap();
-                            let cond = self.get_int().unwrap();
-                            if cond != 0 {
-                                if let Value::Block(ref b) = block {
-                                    let mut istate_new = InterpreterState {
-                                        stack: Vec::new(),
-                                        vars: self.vars.to_owned(),
-                                        globals: self.globals.to_owned(),
-                                        delims: Vec::new(),
-                                        ext_fns: self.ext_fns
-                                    };
-                                    istate_new.run(b);
-                                    for var in self.vars.iter_mut() {
-                                        *var.1 = istate_new.get_var(var.0).unwrap().clone();
-                                    }
-                                    self.globals = istate_new.globals;
-                                } else {
-                                    println!("{:?}", self);
-                                    panic!("not a block {:?}", block);
-                                }
-                            }
-                        }
+                    if let Some(Value::Keyword(Keyword::Let | Keyword::Global)) = vals.get(i + 1) {
+                        panic!("{} is a keyword, it cant be used as a name", word_name(val));
+                    }
+                    self.check_underflow(val, keyword_effect(kw).map_or(1, |(n, _)| n));
+                    let flow = match kw {
+                        Keyword::If => self.exec_if(),
+                        Keyword::For | Keyword::ForCollect => self.exec_for(kw),
+                        Keyword::Loop => self.exec_loop(),
+                        Keyword::Times => self.exec_times(),
+                        _ => self.exec_keyword(kw),
+                    };
+                    if flow != Flow::Normal {
+                        return flow;
                     }
                 }
                 Value::Tuple(_) => {}
                 Value::Block(_) => {}
                 Value::Array(_) => {}
                 Value::None => {}
+                Value::Unset => {}
             }
         }
+        Flow::Normal
     }
-
-}
-
-pub fn tokenize(fortnite: &str) -> Vec<Value> {
-    let mut cur_val = Value::None;
-    let mut cur_str = String::new();
-    let mut vals = vec![];
-    for ch in fortnite.chars() {
-        match cur_val {
-            Value::None => {
-                if ch.is_numeric() {
-                    cur_val = Value::Int(0);
-                    cur_str.push(ch);
-                } else if ch.is_ascii_alphabetic() {
-                    cur_val = Value::Ident(String::new());
-                    cur_str.push(ch);
-                } else if ch == '"' {
-                    cur_val = Value::String(String::new());
-                    // cur_str.push(ch);
-                } else if ch == ' ' || ch == '\n' {
-                    cur_str.clear();
-                } else {
-                    let op =
-                        match ch {
-                            '+' => {Op::Add}
-                            '-' => {Op::Sub}
-                            '*' => {Op::Mul}
-                            '/' => {Op::Div}
-                            '%' => {Op::Mod}
-                            '=' => {Op::Assign}
-                            '!' => {Op::Invert}
-                            '(' => {Op::TupleStart}
-                            ')' => {Op::TupleEnd}
-                            '{' => {Op::BlockStart}
-                            '}' => {Op::BlockEnd}
-                            '[' => {Op::ArrayStart}
-                            ']' => {Op::ArrayEnd}
-                            '@' => {Op::CallFn}
-                            '#' => {Op::IndexArray}
-                            _ => {panic!("invalid char {}", ch)}
+    /// runs any op but @, anything other than Flow::Normal ends the scope
+    fn exec_op(&mut self, op: &Op) -> Flow {
+        match op {
+            Op::Assign => {
+                // ranges stay lazy in vars too
+                let v = self.pop_value().map(|v| self.resolve(v)).unwrap();
+                // storing the bare name would quietly make an alias to nothing
+                if let Value::Ident(ref i) = v {
+                    panic!("cant assign {}, it isnt defined", i);
+                }
+                let target = self.stack.pop().unwrap();
+                if let Value::Ident(k) = target {
+                    // a tuple literal is run now, so the var holds what its names meant at this point
+                    let (v, flow) = self.eval_tuple(v);
+                    if flow != Flow::Normal {
+                        return flow;
+                    }
+                    self.set_var(&k, v);
+                    // println!("set var {} to value {:?}", &k, v);
+                } else if let Value::Tuple(names) = target {
+                    // ( a b ) ( 1 2 ) = sets each name from the same spot
+                    let (v, flow) = self.eval_tuple(v);
+                    if flow != Flow::Normal {
+                        return flow;
+                    }
+                    let vs = match v {
+                        Value::Tuple(vs) => vs,
+                        Value::Array(vs) => Arc::unwrap_or_clone(vs),
+                        v => {
+                            panic!("cant assign {} to a tuple of names", v);
+                        }
+                    };
+                    if vs.len() != names.len() {
+                        panic!("cant assign {} values to {} names", vs.len(), names.len());
+                    }
+                    for (name, v) in names.into_iter().zip(vs) {
+                        let Value::Ident(k) = name else {
+                            panic!("can only assign to idents, got {}", name);
                         };
-                    cur_val = Value::Operation(op);
+                        let v = self.resolve(v);
+                        if let Value::Ident(ref i) = v {
+                            panic!("cant assign {}, it isnt defined", i);
+                        }
+                        self.set_var(&k, v);
+                    }
+                } else {
+                    panic!("= needs a variable or a tuple of names to set, got {}", target);
                 }
             }
-            Value::Int(_) => {
-                if !ch.is_numeric() {
-                    vals.push(Value::Int(cur_str.parse().unwrap()));
-                    cur_str.clear();
-                    cur_val = Value::None;
-                    continue;
+            Op::SetAll => {
+                // either ( a b c ) or a b c 3, the names come in the same order as the values
+                let names = match self.stack.pop().unwrap() {
+                    Value::Tuple(t) => t,
+                    Value::Int(n) => {
+                        if n < 0 || n as usize > self.stack.len() {
+                            panic!("set_all needs {} names, the stack has {} values", n, self.stack.len());
+                        }
+                        let at = self.stack.len() - n as usize;
+                        self.stack.split_off(at)
+                    }
+                    v => {
+                        panic!("set_all needs a tuple of names or a count, got {}", v);
+                    }
+                };
+                if names.len() > self.stack.len() {
+                    panic!("set_all has {} names but only {} values", names.len(), self.stack.len());
+                }
+                let at = self.stack.len() - names.len();
+                let vals = self.stack.split_off(at);
+                for (name, v) in names.into_iter().zip(vals) {
+                    let Value::Ident(k) = name else {
+                        panic!("set_all can only set idents, got {}", name);
+                    };
+                    if self.get_var(&k).is_none() {
+                        panic!("cant set {}, it was never declared", k);
+                    }
+                    let v = self.resolve(v);
+                    if let Value::Ident(ref i) = v {
+                        panic!("cant assign {}, it isnt defined", i);
+                    }
+                    self.set_var(&k, v);
                 }
-                cur_str.push(ch);
             }
-            Value::String(_) => {
-                if ch == '"' {
-                    vals.push(Value::String(cur_str.clone()));
-                    cur_str.clear();
-                    cur_val = Value::None;
-                    continue;
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Min | Op::Max => {
+                let b = self.get_value().unwrap();
+                let a = self.get_value().unwrap();
+                let v = self.arith(op, a, b);
+                self.push_value(v);
+            }
+            Op::Invert => {
+                let a = self.get_int().unwrap();
+                self.push_value(Value::Int(if a != 0 { 0 } else { 1 }));
+            }
+            Op::Abs => {
+                let a = self.get_int().unwrap();
+                let Some(abs) = a.checked_abs() else {
+                    panic!("abs of {} doesnt fit in an int", a);
+                };
+                self.push_value(Value::Int(abs));
+            }
+            Op::Gcd | Op::Lcm => {
+                let b = self.get_int().unwrap();
+                let a = self.get_int().unwrap();
+                if a == 0 && b == 0 {
+                    panic!("{} of 0 and 0 isnt defined", op_name(op));
                 }
-                cur_str.push(ch);
+                let g = gcd(a.unsigned_abs() as u64, b.unsigned_abs() as u64);
+                // always positive, like most math libraries do it
+                let r = if let Op::Gcd = op {
+                    g
+                } else {
+                    a.unsigned_abs() as u64 / g * b.unsigned_abs() as u64
+                };
+                let Ok(r) = i32::try_from(r) else {
+                    panic!("{} of {} and {} doesnt fit in an int", op_name(op), a, b);
+                };
+                self.push_value(Value::Int(r));
             }
-            Value::Ident(_) => {
-                if !ch.is_alphanumeric() {
-                    match cur_str.as_str() {
-                        "let" => {
-                            vals.push(Value::Keyword(Keyword::Let));
+            Op::ModPow => {
+                let m = self.get_int().unwrap();
+                let exp = self.get_int().unwrap();
+                let base = self.get_int().unwrap();
+                if m <= 0 || exp < 0 {
+                    panic!("modpow needs a positive modulus and an exponent of at least 0, got {} and {}", m, exp);
+                }
+                // squaring in i64 cant overflow since everything is below m
+                let m = m as i64;
+                let mut b = (base as i64).rem_euclid(m);
+                let mut e = exp;
+                let mut r = 1 % m;
+                while e > 0 {
+                    if e & 1 == 1 {
+                        r = r * b % m;
+                    }
+                    b = b * b % m;
+                    e >>= 1;
+                }
+                self.push_value(Value::Int(r as i32));
+            }
+            Op::Clamp => {
+                let high = self.get_int().unwrap();
+                let low = self.get_int().unwrap();
+                let v = self.get_int().unwrap();
+                if low > high {
+                    panic!("cant clamp to {}..{}, low is bigger than high", low, high);
+                }
+                self.push_value(Value::Int(v.clamp(low, high)));
+            }
+            Op::Equal => {
+                let b = self.get_value().unwrap();
+                let a = self.get_value().unwrap();
+                self.push_value(Value::Int(deep_eq(&a, &b) as i32));
+            }
+            Op::Less | Op::Greater | Op::LessEq | Op::GreaterEq => {
+                let b = self.get_value().unwrap();
+                let a = self.get_value().unwrap();
+                // the same ordering sort uses, so tuples go field by field
+                let Some(ord) = compare(&a, &b) else {
+                    panic!("cant compare {} and {}", a, b);
+                };
+                let yes = match op {
+                    Op::Less => ord.is_lt(),
+                    Op::Greater => ord.is_gt(),
+                    Op::LessEq => ord.is_le(),
+                    _ => ord.is_ge(),
+                };
+                self.push_value(Value::Int(yes as i32));
+            }
+            Op::Copy => {
+                // resolving the ident already gives a copy, an array only copies its elements once one side changes
+                let v = self.get_value().unwrap();
+                self.push_value(v);
+            }
+            Op::IsNone => {
+                let v = self.get_value().unwrap();
+                self.push_value(Value::Int((v == Value::None) as i32));
+            }
+            Op::OrElse => {
+                let default = self.get_value().unwrap();
+                let v = self.get_value().unwrap();
+                if let Value::None = v {
+                    self.push_value(default);
+                } else {
+                    self.push_value(v);
+                }
+            }
+            Op::ReadFile => {
+                if let Value::String(path) = self.get_value().unwrap() {
+                    let contents = fs::read_to_string(path)
+                        .map(Value::String)
+                        .unwrap_or(Value::None);
+                    self.push_value(contents);
+                } else {
+                    panic!("read_file needs a path string");
+                }
+            }
+            Op::ReadBytes => {
+                if let Value::String(path) = self.get_value().unwrap() {
+                    let contents = fs::read(path)
+                        .map(Value::Bytes)
+                        .unwrap_or(Value::None);
+                    self.push_value(contents);
+                } else {
+                    panic!("read_bytes needs a path string");
+                }
+            }
+            Op::WriteFile => {
+                let contents = self.get_value().unwrap();
+                if let Value::String(path) = self.get_value().unwrap() {
+                    // bytes go out as they are, everything else as it would print
+                    let ok = if let Value::Bytes(b) = contents {
+                        fs::write(path, b).is_ok()
+                    } else {
+                        fs::write(path, contents.to_string()).is_ok()
+                    };
+                    self.push_value(Value::Int(ok as i32));
+                } else {
+                    panic!("write_file needs a path string");
+                }
+            }
+            Op::Sum | Op::Product => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut acc: i32 = if let Op::Sum = op { 0 } else { 1 };
+                    for v in a.iter() {
+                        let Some(i) = self.as_int(v.clone()) else {
+                            panic!("cant {} a non-int {}", op_name(op), v);
+                        };
+                        let next = if let Op::Sum = op { acc.checked_add(i) } else { acc.checked_mul(i) };
+                        let Some(next) = next else {
+                            panic!("{} of the array doesnt fit in an int", op_name(op));
+                        };
+                        acc = next;
+                    }
+                    self.push_value(Value::Int(acc));
+                } else {
+                    panic!("{} needs an array", op_name(op));
+                }
+            }
+            Op::Count => {
+                match self.get_value().unwrap() {
+                    Value::Array(a) => {
+                        self.push_value(Value::Int(a.len() as i32));
+                    }
+                    Value::String(s) => {
+                        self.push_value(Value::Int(s.chars().count() as i32));
+                    }
+                    Value::Bytes(b) => {
+                        self.push_value(Value::Int(b.len() as i32));
+                    }
+                    v => {
+                        panic!("cant count {}", v);
+                    }
+                }
+            }
+            Op::ToArray => {
+                // get_value already turned a range into an array
+                let v = self.get_value().unwrap();
+                if let Value::Array(_) = v {
+                    self.push_value(v);
+                } else {
+                    panic!("to_array needs a range or an array, got {}", v);
+                }
+            }
+            Op::IsEmpty => {
+                let empty = match self.get_value().unwrap() {
+                    Value::Array(a) => a.is_empty(),
+                    Value::Tuple(a) => a.is_empty(),
+                    Value::String(s) => s.is_empty(),
+                    Value::Bytes(b) => b.is_empty(),
+                    v => {
+                        panic!("cant check if {} is empty", v);
+                    }
+                };
+                self.push_value(Value::Int(empty as i32));
+            }
+            Op::MinBy | Op::MaxBy => {
+                let f = self.get_value().unwrap();
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut best: Option<(i32, Value)> = None;
+                    for v in Arc::unwrap_or_clone(a) {
+                        let (key, flow) = self.apply(&f, v.clone());
+                        if let Flow::Exit(_) | Flow::Timeout = flow {
+                            return flow;
                         }
-                        "global" => {
-                            vals.push(Value::Keyword(Keyword::Global));
+                        let Some(k) = self.as_int(key.clone()) else {
+                            panic!("{} key fn must give an int, got {}", op_name(op), key);
+                        };
+                        let better = match best {
+                            None => true,
+                            Some((bk, _)) => if let Op::MinBy = op { k < bk } else { k > bk },
+                        };
+                        if better {
+                            best = Some((k, v));
                         }
-                        "print" => {
-                            vals.push(Value::Keyword(Keyword::Print));
+                    }
+                    // an empty array has no min or max
+                    self.push_value(best.map(|(_, v)| v).unwrap_or(Value::None));
+                } else {
+                    panic!("{} needs an array", op_name(op));
+                }
+            }
+            Op::Enumerate => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let pairs = Arc::unwrap_or_clone(a).into_iter()
+                        .enumerate()
+                        .map(|(i, v)| Value::Tuple(vec![Value::Int(i as i32), v]))
+                        .collect();
+                    self.push_value(Value::Array(Arc::new(pairs)));
+                } else {
+                    panic!("enumerate needs an array");
+                }
+            }
+            Op::Range | Op::RangeStep => {
+                let step = if let Op::RangeStep = op {
+                    Some(self.get_int().unwrap())
+                } else {
+                    None
+                };
+                let end = self.get_int().unwrap();
+                let start = self.get_int().unwrap();
+                // without a step, count down when start is past end
+                let step = step.unwrap_or(if start > end { -1 } else { 1 });
+                if step == 0 {
+                    panic!("range step cant be 0");
+                }
+                self.push_value(Value::Range { start, end, step });
+            }
+            Op::Join => {
+                let sep = self.get_value().unwrap();
+                let array = self.get_value().unwrap();
+                if let (Value::Array(a), Value::String(sep)) = (&array, &sep) {
+                    // anything goes, each element is written the way println would
+                    let strs: Vec<String> = a.iter().map(|v| v.to_string()).collect();
+                    self.push_value(Value::String(strs.join(sep)));
+                } else {
+                    panic!("join needs an array and a string, got {} and {}", array, sep);
+                }
+            }
+            Op::ConcatAll => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut out = String::new();
+                    for v in a.iter() {
+                        match v {
+                            Value::String(s) => out.push_str(s),
+                            Value::Char(c) => out.push(*c),
+                            v => {
+                                panic!("concat_all needs strings, got {}", v);
+                            }
                         }
-                        "println" => {
-                            vals.push(Value::Keyword(Keyword::PrintLn));
+                    }
+                    self.push_value(Value::String(out));
+                } else {
+                    panic!("concat_all needs an array");
+                }
+            }
+            Op::Take | Op::Skip => {
+                let n = self.get_int().unwrap().max(0) as usize;
+                // n past the end is clamped to the length
+                match self.get_value().unwrap() {
+                    Value::Array(a) => {
+                        let a = if let Op::Take = op {
+                            a.iter().take(n).cloned().collect()
+                        } else {
+                            a.iter().skip(n).cloned().collect()
+                        };
+                        self.push_value(Value::Array(Arc::new(a)));
+                    }
+                    Value::String(s) => {
+                        let s = if let Op::Take = op {
+                            s.chars().take(n).collect()
+                        } else {
+                            s.chars().skip(n).collect()
+                        };
+                        self.push_value(Value::String(s));
+                    }
+                    v => {
+                        panic!("cant {} from {}", op_name(op), v);
+                    }
+                }
+            }
+            Op::First | Op::Last => {
+                let v = match self.get_value().unwrap() {
+                    Value::Array(a) => {
+                        if let Op::First = op { a.first().cloned() } else { a.last().cloned() }
+                    }
+                    Value::String(s) => {
+                        let c = if let Op::First = op { s.chars().next() } else { s.chars().last() };
+                        c.map(Value::Char)
+                    }
+                    v => {
+                        panic!("cant get {} of {}", op_name(op), v);
+                    }
+                };
+                self.push_value(v.unwrap_or(Value::None));
+            }
+            Op::Flatten => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut out = vec![];
+                    for v in a.iter() {
+                        match v {
+                            Value::Array(inner) => out.extend(inner.iter().cloned()),
+                            v => out.push(v.clone()),
                         }
-                        "fn" => {
-                            vals.push(Value::Keyword(Keyword::Fn));
+                    }
+                    self.push_value(Value::Array(Arc::new(out)));
+                } else {
+                    panic!("flatten needs an array");
+                }
+            }
+            Op::Random => {
+                let hi = self.get_int().unwrap();
+                let lo = self.get_int().unwrap();
+                if lo >= hi {
+                    panic!("random needs lo < hi, got {} {}", lo, hi);
+                }
+                let span = (hi as i64 - lo as i64) as u64;
+                let r = lo as i64 + (self.next_random() % span) as i64;
+                self.push_value(Value::Int(r as i32));
+            }
+            Op::Now => {
+                // monotonic, so differences are meaningful even if the clock changes
+                let ms = self.started.elapsed().as_millis();
+                self.push_value(Value::Int(ms as i32));
+            }
+            Op::Rot | Op::RRot | Op::Tuck | Op::Dup2 => {
+                // exec already checked there are enough values
+                let len = self.stack.len();
+                match op {
+                    // a b c -- b c a
+                    Op::Rot => self.stack[len - 3..].rotate_left(1),
+                    // a b c -- c a b
+                    Op::RRot => self.stack[len - 3..].rotate_right(1),
+                    // a b -- b a b
+                    Op::Tuck => {
+                        let b = self.stack[len - 1].clone();
+                        self.stack.insert(len - 2, b);
+                    }
+                    // a b -- a b a b
+                    _ => self.stack.extend_from_within(len - 2..),
+                }
+            }
+            Op::Pack => {
+                let n = self.get_int().unwrap() as usize;
+                if self.stack.len() < n {
+                    panic!("stack underflow: cant pack {} values", n);
+                }
+                // packed values can leave the scope, so they are values and not names
+                let vs = self.stack.split_off(self.stack.len() - n);
+                let vs = vs.into_iter().map(|v| self.resolve_deep(v)).collect();
+                self.push_value(Value::Tuple(vs));
+            }
+            Op::Unpack => {
+                match self.get_value().unwrap() {
+                    Value::Tuple(vs) => self.stack.extend(vs),
+                    Value::Array(vs) => self.stack.extend(vs.iter().cloned()),
+                    v => {
+                        panic!("cant unpack {}", v);
+                    }
+                }
+            }
+            Op::CallDepth => {
+                self.push_value(Value::Int(self.depth as i32));
+            }
+            Op::Chars | Op::Bytes => {
+                if let Value::String(s) = self.get_value().unwrap() {
+                    let vs = if let Op::Chars = op {
+                        s.chars().map(Value::Char).collect()
+                    } else {
+                        s.bytes().map(|b| Value::Int(b as i32)).collect()
+                    };
+                    self.push_value(Value::Array(Arc::new(vs)));
+                } else {
+                    panic!("{} needs a string", op_name(op));
+                }
+            }
+            Op::ToBytes => {
+                if let Value::String(s) = self.get_value().unwrap() {
+                    self.push_value(Value::Bytes(s.into_bytes()));
+                } else {
+                    panic!("to_bytes needs a string");
+                }
+            }
+            Op::FromBytes => {
+                if let Value::Bytes(b) = self.get_value().unwrap() {
+                    let s = String::from_utf8(b)
+                        .unwrap_or_else(|e| panic!("from_bytes got invalid utf-8: {}", e));
+                    self.push_value(Value::String(s));
+                } else {
+                    panic!("from_bytes needs bytes");
+                }
+            }
+            Op::AssertStack => {
+                let expected = self.get_int().unwrap();
+                if self.stack.len() != expected as usize {
+                    panic!("assert_stack: expected {} values on the stack, found {}", expected, self.stack.len());
+                }
+            }
+            Op::Splice => {
+                let replacement = self.get_value().unwrap();
+                let end = self.get_int().unwrap();
+                let start = self.get_int().unwrap();
+                let Value::Ident(name) = self.stack.pop().unwrap() else {
+                    panic!("splice needs a variable to change");
+                };
+                let (Some(Value::Array(mut a)), Value::Array(r)) = (self.read_var(&name).cloned().map(to_array), replacement) else {
+                    panic!("splice needs an array variable and a replacement array");
+                };
+                if start < 0 || start > end || end as usize > a.len() {
+                    panic!("splice range {}..{} is out of bounds for {} elements", start, end, a.len());
+                }
+                // let go of the vars copy first, so an array nothing else shares is changed in place
+                self.set_var(&name, Value::Unset);
+                Arc::make_mut(&mut a).splice(start as usize..end as usize, Arc::unwrap_or_clone(r));
+                self.set_var(&name, Value::Array(a));
+            }
+            Op::Partition => {
+                let f = self.get_value().unwrap();
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut yes = vec![];
+                    let mut no = vec![];
+                    for v in Arc::unwrap_or_clone(a) {
+                        let (res, flow) = self.apply(&f, v.clone());
+                        if let Flow::Exit(_) | Flow::Timeout = flow {
+                            return flow;
                         }
-                        "for" => {
-                            vals.push(Value::Keyword(Keyword::For));
+                        let Some(keep) = self.as_int(res.clone()) else {
+                            panic!("partition fn must give an int, got {}", res);
+                        };
+                        if keep != 0 {
+                            yes.push(v);
+                        } else {
+                            no.push(v);
                         }
-                        "if" => {
-                            vals.push(Value::Keyword(Keyword::If));
+                    }
+                    self.push_value(Value::Array(Arc::new(yes)));
+                    self.push_value(Value::Array(Arc::new(no)));
+                } else {
+                    panic!("partition needs an array");
+                }
+            }
+            Op::Any | Op::All => {
+                let f = self.get_value().unwrap();
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    // stops at the first element that settles it, so an empty
+                    // array is false for any and true for all
+                    let want = matches!(op, Op::Any);
+                    let mut found = !want;
+                    for v in a.iter() {
+                        let (res, flow) = self.apply(&f, v.clone());
+                        if let Flow::Exit(_) | Flow::Timeout = flow {
+                            return flow;
                         }
-                        _ => {
-                            vals.push(Value::Ident(cur_str.clone()));
+                        let Some(hit) = self.as_int(res.clone()) else {
+                            panic!("{} fn must give an int, got {}", op_name(op), res);
+                        };
+                        if (hit != 0) == want {
+                            found = want;
+                            break;
                         }
                     }
-                    cur_str.clear();
-                    cur_val = Value::None;
-                    continue;
+                    self.push_value(Value::Int(found as i32));
+                } else {
+                    panic!("{} needs an array", op_name(op));
                 }
-                cur_str.push(ch);
             }
-            Value::Operation(ref cop) => {
-                let op = match ch {
-                    '=' => {
-                        match cop {
-                            Op::Add => {
-                                Op::AddAssign
-                            }
-                            Op::Sub => {
-                                Op::SubAssign
-                            }
-                            Op::Mul => {
-                                Op::MulAssign
+            Op::Scan => {
+                let f = self.get_value().unwrap();
+                let mut acc = self.get_value().unwrap();
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    // the seed itself isnt in the result, so it is as long as the input
+                    let mut steps = Vec::with_capacity(a.len());
+                    for v in Arc::unwrap_or_clone(a) {
+                        acc = match f {
+                            Value::Fn(ref f) => {
+                                let (mut ret, flow) = self.call_fn(f, vec![acc, v]);
+                                if let Flow::Exit(_) | Flow::Timeout = flow {
+                                    return flow;
+                                }
+                                ret.pop().unwrap_or(Value::None)
                             }
-                            Op::Div => {
-                                Op::DivAssign
+                            // ext fns only take one value, so they get both as a tuple
+                            Value::ExtFn(ref name) => {
+                                self.call_ext_fn(name, Value::Tuple(vec![acc, v]))
                             }
                             _ => {
-                                panic!("invalid operator");
+                                panic!("cant scan with non-fn {}", f);
                             }
+                        };
+                        steps.push(acc.clone());
+                    }
+                    self.push_value(Value::Array(Arc::new(steps)));
+                } else {
+                    panic!("scan needs an array");
+                }
+            }
+            Op::IsDigit | Op::IsAlpha | Op::IsAlnum | Op::IsSpace => {
+                let Value::Char(c) = self.get_value().unwrap() else {
+                    panic!("{} needs a char", op_name(op));
+                };
+                // digits are ascii only, letters can be any unicode letter
+                let yes = match op {
+                    Op::IsDigit => c.is_ascii_digit(),
+                    Op::IsAlpha => c.is_alphabetic(),
+                    Op::IsAlnum => c.is_alphanumeric(),
+                    _ => c.is_whitespace(),
+                };
+                self.push_value(Value::Int(yes as i32));
+            }
+            Op::StartsWith | Op::EndsWith | Op::Contains => {
+                let needle = self.get_value().unwrap();
+                let yes = match self.get_value().unwrap() {
+                    // an array contains a value when one of its elements is == to it
+                    Value::Array(a) if matches!(op, Op::Contains) => a.iter().any(|v| deep_eq(v, &needle)),
+                    Value::String(s) => {
+                        let pat = match needle {
+                            Value::String(p) => p,
+                            Value::Char(c) => c.to_string(),
+                            v => panic!("{} needs a string or char to look for, got {}", op_name(op), v),
+                        };
+                        match op {
+                            Op::StartsWith => s.starts_with(&pat),
+                            Op::EndsWith => s.ends_with(&pat),
+                            _ => s.contains(&pat),
+                        }
+                    }
+                    v if matches!(op, Op::Contains) => panic!("contains needs a string or an array to look in, got {}", v),
+                    v => panic!("{} needs a string to look in, got {}", op_name(op), v),
+                };
+                self.push_value(Value::Int(yes as i32));
+            }
+            Op::Upper | Op::Lower | Op::Trim => {
+                if let Value::String(s) = self.get_value().unwrap() {
+                    let s = match op {
+                        Op::Upper => s.to_uppercase(),
+                        Op::Lower => s.to_lowercase(),
+                        _ => s.trim().to_string(),
+                    };
+                    self.push_value(Value::String(s));
+                } else {
+                    panic!("{} needs a string", op_name(op));
+                }
+            }
+            Op::Builtins => {
+                let mut ext: Vec<&String> = self.ext_fns.keys()
+                    .chain(self.multi_ext_fns.into_iter().flat_map(|m| m.keys()))
+                    .collect();
+                ext.sort();
+                let names = SYMBOLS.iter().copied()
+                    .chain(WORDS.iter().map(|(w, _)| *w))
+                    .map(|n| Value::String(n.to_string()))
+                    .chain(ext.into_iter().map(|n| Value::String(n.clone())))
+                    .collect();
+                self.push_value(Value::Array(Arc::new(names)));
+            }
+            Op::AMin | Op::AMax => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut best: Option<i32> = None;
+                    for v in a.iter() {
+                        let Some(i) = self.as_int(v.clone()) else {
+                            panic!("{} needs an array of ints, got {}", op_name(op), v);
+                        };
+                        best = Some(match best {
+                            None => i,
+                            Some(b) => if let Op::AMin = op { b.min(i) } else { b.max(i) },
+                        });
+                    }
+                    let Some(best) = best else {
+                        panic!("{} of an empty array", op_name(op));
+                    };
+                    self.push_value(Value::Int(best));
+                } else {
+                    panic!("{} needs an array", op_name(op));
+                }
+            }
+            Op::ToInt => {
+                let i = match self.get_value().unwrap() {
+                    Value::Int(i) => i,
+                    Value::Char(c) => c as i32,
+                    Value::String(s) => match s.trim().parse() {
+                        Ok(i) => i,
+                        Err(_) => {
+                            panic!("cant turn {:?} into an int", s);
+                        }
+                    },
+                    v => {
+                        panic!("cant turn {} into an int", v);
+                    }
+                };
+                self.push_value(Value::Int(i));
+            }
+            Op::ToBool => {
+                let b = match self.get_value().unwrap() {
+                    Value::Int(i) => i != 0,
+                    Value::String(s) => !s.is_empty(),
+                    Value::Array(a) => !a.is_empty(),
+                    Value::Tuple(a) => !a.is_empty(),
+                    Value::None => false,
+                    v => {
+                        panic!("cant turn {} into a bool", v);
+                    }
+                };
+                self.push_value(Value::Int(b as i32));
+            }
+            Op::PadLeft | Op::PadRight => {
+                let Some(width) = self.get_int() else {
+                    panic!("{} needs an int width", op_name(op));
+                };
+                let v = self.get_value().unwrap();
+                let (v, flow) = self.eval_tuple(v);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+                let v = v.to_string();
+                let width = width.max(0) as usize;
+                // anything already wider is left alone, never cut down
+                let s = if let Op::PadLeft = op {
+                    format!("{:>width$}", v)
+                } else {
+                    format!("{:<width$}", v)
+                };
+                self.push_value(Value::String(s));
+            }
+            Op::Hex | Op::Bin => {
+                let Some(i) = self.get_int() else {
+                    panic!("{} needs an int", op_name(op));
+                };
+                // a sign instead of twos complement, so -255 hex is -ff
+                let sign = if i < 0 { "-" } else { "" };
+                let n = i.unsigned_abs();
+                let s = if let Op::Hex = op {
+                    format!("{}{:x}", sign, n)
+                } else {
+                    format!("{}{:b}", sign, n)
+                };
+                self.push_value(Value::String(s));
+            }
+            Op::Sort | Op::SortDesc => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let mut a = Arc::unwrap_or_clone(a);
+                    if let Some(v) = a.iter().find(|v| compare(&a[0], v).is_none()) {
+                        panic!("cant sort {} along with {}", v, a[0]);
+                    }
+                    // tuples can still clash further in, past the first field
+                    a.sort_by(|x, y| compare(x, y)
+                        .unwrap_or_else(|| panic!("cant sort {} along with {}", x, y)));
+                    if let Op::SortDesc = op {
+                        a.reverse();
+                    }
+                    self.push_value(Value::Array(Arc::new(a)));
+                } else {
+                    panic!("{} needs an array", op_name(op));
+                }
+            }
+            Op::Uniq | Op::DedupAll => {
+                if let Value::Array(a) = self.get_value().unwrap() {
+                    let a = Arc::unwrap_or_clone(a);
+                    if let Some(v) = a.iter().find(|v| !v.is_hashable()) {
+                        panic!("{} cant compare {}", op_name(op), v);
+                    }
+                    let mut out: Vec<Value> = vec![];
+                    let mut seen = std::collections::HashSet::new();
+                    for v in a {
+                        let dup = if let Op::Uniq = op {
+                            out.last().is_some_and(|l| deep_eq(l, &v))
+                        } else {
+                            !seen.insert(v.clone())
+                        };
+                        if !dup {
+                            out.push(v);
                         }
                     }
+                    self.push_value(Value::Array(Arc::new(out)));
+                } else {
+                    panic!("{} needs an array", op_name(op));
+                }
+            }
+            Op::Reverse => {
+                match self.get_value().unwrap() {
+                    Value::Array(mut a) => {
+                        Arc::make_mut(&mut a).reverse();
+                        self.push_value(Value::Array(a));
+                    }
+                    Value::String(s) => {
+                        self.push_value(Value::String(s.chars().rev().collect()));
+                    }
+                    v => {
+                        panic!("cant reverse {}", v);
+                    }
+                }
+            }
+            Op::BlockStart => {
+                self.delims.push(Delim::Block(Vec::new()));
+            }
+            Op::TupleStart => {
+                self.delims.push(Delim::Tuple(Vec::new()));
+            }
+            Op::ArrayStart => {
+                self.delims.push(Delim::Array(Vec::new()));
+            }
+            Op::Nth => {
+                let index = self.get_int().unwrap();
+                // like # but out of range (or negative) gives none instead of stopping
+                let i = usize::try_from(index).ok();
+                let v = match self.get_value().unwrap() {
+                    Value::Array(a) => i.and_then(|i| a.get(i).cloned()),
+                    Value::Tuple(a) => i.and_then(|i| a.into_iter().nth(i)),
+                    Value::String(s) => i.and_then(|i| s.chars().nth(i)).map(Value::Char),
+                    Value::Bytes(b) => i.and_then(|i| b.get(i).map(|b| Value::Int(*b as i32))),
+                    v => {
+                        panic!("cant nth {}", v);
+                    }
+                };
+                self.push_value(v.unwrap_or(Value::None));
+            }
+            Op::IndexArray => {
+                let index = self.get_int().unwrap();
+                let array = self.get_value().unwrap();
+                let len = match array {
+                    Value::Array(ref a) => a.len(),
+                    Value::String(ref a) => a.len(),
+                    Value::Bytes(ref b) => b.len(),
                     _ => {
-                        vals.push(cur_val);
-                        cur_str.clear();
-                        cur_val = Value::None;
-                        continue;
+                        panic!("# needs an array, string or bytes, got {}", array);
                     }
                 };
-                cur_val = Value::Operation(op);
+                if index < 0 || index as usize >= len {
+                    panic!("# index {} is out of range for {} elements", index, len);
+                }
+                if let Value::Array(a) = array {
+                    self.push_value(a[index as usize].clone());
+                } else if let Value::String(a) = array {
+                    self.push_value(Value::Char(a.as_bytes()[index as usize].into()));
+                } else if let Value::Bytes(b) = array {
+                    self.push_value(Value::Int(b[index as usize] as i32));
+                }
             }
             _ => {}
         }
+        Flow::Normal
+    }
+    fn exec_keyword(&mut self, kw: &Keyword) -> Flow {
+        match kw {
+            Keyword::Let => {
+                match self.stack.pop().unwrap() {
+                    Value::Ident(i) => {
+                        self.add_var(&i);
+                        // println!("added var {}", &i);
+                        self.push_value(Value::Ident(i));
+                    }
+                    // ( a b c ) let declares all of them at once
+                    Value::Tuple(t) => {
+                        for v in &t {
+                            if let Value::Ident(i) = v {
+                                self.add_var(i);
+                            } else {
+                                panic!("let needs a tuple of idents, got {}", v);
+                            }
+                        }
+                        self.push_value(Value::Tuple(t));
+                    }
+                    v => {
+                        panic!("let needs an ident or a tuple of idents, got {}", v);
+                    }
+                }
+            }
+            Keyword::Global => {
+                match self.stack.pop().unwrap() {
+                    Value::Ident(i) => {
+                        self.add_global(&i);
+                        // println!("added var {}", &i);
+                        self.push_value(Value::Ident(i));
+                    }
+                    v => {
+                        panic!("global needs an ident, got {}", v);
+                    }
+                }
+            }
+            Keyword::Defined => {
+                let name = match self.stack.pop().unwrap() {
+                    Value::Ident(i) | Value::String(i) => i,
+                    v => {
+                        panic!("defined needs an ident or a string, got {}", v);
+                    }
+                };
+                let defined = self.get_var(&name).is_some();
+                self.push_value(Value::Int(defined as i32));
+            }
+            Keyword::Undef => {
+                match self.stack.pop().unwrap() {
+                    Value::Ident(i) => {
+                        let removed = self.remove_var(&i).is_some();
+                        self.push_value(Value::Int(removed as i32));
+                    }
+                    v => {
+                        panic!("undef needs an ident, got {}", v);
+                    }
+                }
+            }
+            Keyword::SwapVars | Keyword::Rename => {
+                let (Value::Ident(b), Value::Ident(a)) = (self.stack.pop().unwrap(), self.stack.pop().unwrap()) else {
+                    panic!("{} needs two idents", keyword_name(kw));
+                };
+                let Some(av) = self.read_var(&a).cloned() else {
+                    panic!("cant {} {}, it isnt defined", keyword_name(kw), a);
+                };
+                if let Keyword::SwapVars = kw {
+                    let Some(bv) = self.read_var(&b).cloned() else {
+                        panic!("cant swap_vars {}, it isnt defined", b);
+                    };
+                    self.set_var(&a, bv);
+                    self.set_var(&b, av);
+                } else {
+                    if self.get_var(&b).is_some() {
+                        panic!("cant rename {} to {}, {} already exists", a, b, b);
+                    }
+                    // a global stays a global under its new name
+                    if self.vars.remove(&a).is_some() {
+                        if self.depth > 0 {
+                            self.renamed.push((a, b.clone()));
+                        }
+                        self.vars.insert(b, av);
+                    } else {
+                        self.globals.remove(&a);
+                        self.globals.insert(b, av);
+                    }
+                }
+            }
+            Keyword::Fn => {
+                let f = self.pop_fn("fn");
+                self.push_value(Value::Fn(f));
+            }
+            Keyword::Def => {
+                let mut f = self.pop_fn("def");
+                let Value::Ident(name) = self.stack.pop().unwrap() else {
+                    panic!("def needs a name before the ( args ) tuple");
+                };
+                f.name = Some(name.clone());
+                self.add_var(&name);
+                self.set_var(&name, Value::Fn(f));
+            }
+            Keyword::Print => {
+                let v = self.get_value().unwrap();
+                let (v, flow) = self.eval_tuple(v);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+                write!(self.out.0, "{}", v).unwrap();
+            }
+            Keyword::PrintLn => {
+                let v = self.get_value().unwrap();
+                let (v, flow) = self.eval_tuple(v);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+                writeln!(self.out.0, "{}", v).unwrap();
+            }
+            Keyword::PrintN => {
+                let n = self.get_int().unwrap();
+                if n < 0 || n as usize > self.stack.len() {
+                    panic!("cant print {} values, the stack has {}", n, self.stack.len());
+                }
+                let at = self.stack.len() - n as usize;
+                let vs = self.stack.split_off(at);
+                let flow = self.print_all(vs);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+            }
+            Keyword::PrintStack => {
+                // for debugging, so the stack is left as it was
+                let vs = self.stack.clone();
+                let flow = self.print_all(vs);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+            }
+            Keyword::PrintJoined => {
+                let sep = self.get_value().unwrap();
+                let array = self.get_value().unwrap();
+                if let (Value::Array(a), Value::String(sep)) = (&array, &sep) {
+                    let strs: Vec<String> = a.iter().map(|v| v.to_string()).collect();
+                    write!(self.out.0, "{}", strs.join(sep)).unwrap();
+                } else {
+                    panic!("print_joined needs an array and a string, got {} and {}", array, sep);
+                }
+            }
+            Keyword::Capture => {
+                if let Value::Block(body) = self.get_value().unwrap() {
+                    // a snapshot, later changes to these vars arent seen and changes inside dont leak out
+                    let env = self.vars.clone();
+                    self.push_value(Value::Fn(Fn { args: vec![], body: body.into(), env, name: None }));
+                } else {
+                    panic!("capture needs a block");
+                }
+            }
+            Keyword::Break => {
+                return Flow::Break;
+            }
+            Keyword::Defer => {
+                match self.get_value().unwrap() {
+                    Value::Block(b) => self.deferred.push(b),
+                    v => panic!("defer needs a block, got {}", v),
+                }
+            }
+            Keyword::Eval => {
+                if let Value::String(src) = self.get_value().unwrap() {
+                    let program = Program::parse(&src)
+                        .unwrap_or_else(|e| panic!("cant eval {:?}: {}", src, e));
+                    // runs right here, so anything it defines stays around afterwards
+                    let flow = self.exec(program.values());
+                    if flow != Flow::Normal {
+                        return flow;
+                    }
+                } else {
+                    panic!("eval needs a string");
+                }
+            }
+            Keyword::Exit => {
+                let code = self.get_int().unwrap();
+                return Flow::Exit(code);
+            }
+            // run straight from exec, see there
+            Keyword::If | Keyword::For | Keyword::ForCollect | Keyword::Loop | Keyword::Times => {
+                unreachable!("{:?} is run by exec", kw);
+            }
+        }
+        Flow::Normal
+    }
+    /// stops before running a word that needs more values than the stack has
+    fn check_underflow(&self, word: &Value, needed: usize) {
+        if self.stack.len() < needed {
+            let s = if needed == 1 { "" } else { "s" };
+            panic!("stack underflow: {} needs {} value{}, the stack has {}", word_name(word), needed, s, self.stack.len());
+        }
+    }
+    fn exec_call(&mut self) -> Flow {
+        let name = if let Some(Value::Ident(i)) = self.stack.last() {
+            Some(i.clone())
+        } else {
+            None
+        };
+        match self.get_value().unwrap() {
+            Value::Fn(f) => {
+                if self.stack.len() < f.args.len() {
+                    let name = name.as_ref().or(f.name.as_ref()).map_or("fn", |n| n.as_str());
+                    panic!("stack underflow: {} needs {} args, the stack has {}", name, f.args.len(), self.stack.len());
+                }
+                // the top of the stack is the last arg, so `1 2 f @` binds ( a b ) to 1 2
+                let mut args: Vec<Value> = f.args.iter()
+                    .map(|_| self.get_value().unwrap())
+                    .collect();
+                args.reverse();
+                // whatever the fn leaves on its stack is returned, so it can return several values
+                let (ret, flow) = self.call_fn(&f, args);
+                self.stack.extend(ret);
+                if let Flow::Exit(_) | Flow::Timeout = flow {
+                    return flow;
+                }
+            }
+            // a block runs inline like the body of an if
+            Value::Block(b) => {
+                let flow = self.run_block(&b);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+            }
+            // TODO improvements needed
+            Value::ExtFn(ref f) => {
+                let val = self.get_value().unwrap_or(Value::None);
+                let res = self.call_ext_fn_multi(f, val);
+                self.stack.extend(res);
+            }
+            v => {
+                match name {
+                    // vars always win over ext fns, even when they arent callable
+                    Some(n) if self.has_ext_fn(&n) => {
+                        panic!("cant call {}: the variable {} = {} hides the ext fn of the same name", n, n, v);
+                    }
+                    _ => panic!("cant call non-fn {}", v),
+                }
+            }
+        }
+        Flow::Normal
+    }
+    fn exec_if(&mut self) -> Flow {
+        let block = self.get_value().unwrap();
+        let cond = match self.get_value().unwrap() {
+            // a condition block runs first and its top value decides
+            Value::Block(c) => {
+                let mut istate_new = self.child(self.vars.clone());
+                let flow = istate_new.run(&c);
+                let top = istate_new.stack.pop().map(|v| istate_new.resolve(v));
+                self.write_back(&istate_new);
+                self.absorb(&mut istate_new);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+                top.and_then(|v| self.as_int(v))
+            }
+            v => self.as_int(v),
+        };
+        let Some(cond) = cond else {
+            panic!("if condition must be an int or a block giving one");
+        };
+        if cond != 0 {
+            if let Value::Block(ref b) = block {
+                let flow = self.run_block(b);
+                if flow != Flow::Normal {
+                    return flow;
+                }
+            } else {
+                panic!("if needs a block, got {}", block);
+            }
+        }
+        Flow::Normal
+    }
+    fn exec_for(&mut self, kw: &Keyword) -> Flow {
+        let block = self.get_value().unwrap();
+        let val_name = self.pop_value().unwrap();
+        // array literals are already evaluated when their ] is reached
+        let array = self.pop_value().map(|v| self.resolve(v)).unwrap();
+        // ranges are walked one int at a time instead of being built up front
+        let items: Box<dyn Iterator<Item = Value>> = match array {
+            Value::Array(a) => Box::new(Arc::unwrap_or_clone(a).into_iter()),
+            Value::Range { start, end, step } => {
+                Box::new(range_iter(start, end, step).map(Value::Int))
+            }
+            v => {
+                panic!("{} needs an array or a range, got {}", keyword_name(kw), v);
+            }
+        };
+        let mut istate_new = self.child(self.vars.clone());
+        let mut flow = Flow::Normal;
+        let mut collected = vec![];
+        if let Value::Ident(ref i) = val_name {
+            if let Value::Block(ref b) = block {
+                istate_new.add_var(i);
+                for val in items {
+                    istate_new.set_var(i, val);
+                    flow = istate_new.run(b);
+                    // for_collect keeps the top value of each pass,
+                    // a pass that leaves nothing adds nothing
+                    if let Keyword::ForCollect = kw {
+                        if let Some(v) = istate_new.stack.pop() {
+                            collected.push(istate_new.resolve(v));
+                        }
+                        istate_new.stack.clear();
+                    }
+                    if flow != Flow::Normal {
+                        break;
+                    }
+                }
+                self.write_back(&istate_new);
+            } else {
+                panic!("{} needs a block, got {}", keyword_name(kw), block);
+            }
+        } else {
+            panic!("{} needs an ident to bind, got {}", keyword_name(kw), val_name);
+        }
+        self.absorb(&mut istate_new);
+        if let Flow::Exit(_) | Flow::Timeout = flow {
+            return flow;
+        }
+        if let Keyword::ForCollect = kw {
+            self.push_value(Value::Array(Arc::new(collected)));
+        }
+        Flow::Normal
+    }
+    fn exec_loop(&mut self) -> Flow {
+        let block = self.get_value().unwrap();
+        if let Value::Block(ref b) = block {
+            let mut istate_new = self.child(self.vars.clone());
+            let flow = loop {
+                let flow = istate_new.run(b);
+                if flow != Flow::Normal {
+                    break flow;
+                }
+            };
+            self.write_back(&istate_new);
+            self.absorb(&mut istate_new);
+            if let Flow::Exit(_) | Flow::Timeout = flow {
+                return flow;
+            }
+        } else {
+            panic!("loop needs a block, got {}", block);
+        }
+        Flow::Normal
+    }
+    fn exec_times(&mut self) -> Flow {
+        let block = self.get_value().unwrap();
+        let n = self.get_int().unwrap();
+        if let Value::Block(ref b) = block {
+            let mut istate_new = self.child(self.vars.clone());
+            let mut flow = Flow::Normal;
+            for _ in 0..n {
+                flow = istate_new.run(b);
+                if flow != Flow::Normal {
+                    break;
+                }
+            }
+            self.write_back(&istate_new);
+            self.absorb(&mut istate_new);
+            if let Flow::Exit(_) | Flow::Timeout = flow {
+                return flow;
+            }
+        } else {
+            panic!("times needs a block, got {}", block);
+        }
+        Flow::Normal
+    }
+
+}
+
+/// every built in word. keywords are picked out by the tokenizer, ops stay idents
+/// and only run as the op when no var, global or ext fn has the same name
+const WORDS: &[(&str, Value)] = &[
+    ("let", Value::Keyword(Keyword::Let)),
+    ("global", Value::Keyword(Keyword::Global)),
+    ("print", Value::Keyword(Keyword::Print)),
+    ("println", Value::Keyword(Keyword::PrintLn)),
+    ("print_n", Value::Keyword(Keyword::PrintN)),
+    ("print_stack", Value::Keyword(Keyword::PrintStack)),
+    ("for_collect", Value::Keyword(Keyword::ForCollect)),
+    ("defer", Value::Keyword(Keyword::Defer)),
+    ("fn", Value::Keyword(Keyword::Fn)),
+    ("print_joined", Value::Keyword(Keyword::PrintJoined)),
+    ("for", Value::Keyword(Keyword::For)),
+    ("if", Value::Keyword(Keyword::If)),
+    ("defined", Value::Keyword(Keyword::Defined)),
+    ("undef", Value::Keyword(Keyword::Undef)),
+    ("loop", Value::Keyword(Keyword::Loop)),
+    ("break", Value::Keyword(Keyword::Break)),
+    ("eval", Value::Keyword(Keyword::Eval)),
+    ("times", Value::Keyword(Keyword::Times)),
+    ("capture", Value::Keyword(Keyword::Capture)),
+    ("def", Value::Keyword(Keyword::Def)),
+    ("swap_vars", Value::Keyword(Keyword::SwapVars)),
+    ("rename", Value::Keyword(Keyword::Rename)),
+    ("exit", Value::Keyword(Keyword::Exit)),
+    ("min", Value::Operation(Op::Min)),
+    ("max", Value::Operation(Op::Max)),
+    ("abs", Value::Operation(Op::Abs)),
+    ("copy", Value::Operation(Op::Copy)),
+    ("is_none", Value::Operation(Op::IsNone)),
+    ("or_else", Value::Operation(Op::OrElse)),
+    ("read_file", Value::Operation(Op::ReadFile)),
+    ("write_file", Value::Operation(Op::WriteFile)),
+    ("sum", Value::Operation(Op::Sum)),
+    ("product", Value::Operation(Op::Product)),
+    ("count", Value::Operation(Op::Count)),
+    ("min_by", Value::Operation(Op::MinBy)),
+    ("max_by", Value::Operation(Op::MaxBy)),
+    ("enumerate", Value::Operation(Op::Enumerate)),
+    ("range", Value::Operation(Op::Range)),
+    ("range_step", Value::Operation(Op::RangeStep)),
+    ("concat_all", Value::Operation(Op::ConcatAll)),
+    ("take", Value::Operation(Op::Take)),
+    ("skip", Value::Operation(Op::Skip)),
+    ("first", Value::Operation(Op::First)),
+    ("last", Value::Operation(Op::Last)),
+    ("flatten", Value::Operation(Op::Flatten)),
+    ("random", Value::Operation(Op::Random)),
+    ("now", Value::Operation(Op::Now)),
+    ("rot", Value::Operation(Op::Rot)),
+    ("rrot", Value::Operation(Op::RRot)),
+    ("tuck", Value::Operation(Op::Tuck)),
+    ("dup2", Value::Operation(Op::Dup2)),
+    ("pack", Value::Operation(Op::Pack)),
+    ("unpack", Value::Operation(Op::Unpack)),
+    ("call_depth", Value::Operation(Op::CallDepth)),
+    ("chars", Value::Operation(Op::Chars)),
+    ("bytes", Value::Operation(Op::Bytes)),
+    ("assert_stack", Value::Operation(Op::AssertStack)),
+    ("splice", Value::Operation(Op::Splice)),
+    ("partition", Value::Operation(Op::Partition)),
+    ("upper", Value::Operation(Op::Upper)),
+    ("lower", Value::Operation(Op::Lower)),
+    ("trim", Value::Operation(Op::Trim)),
+    ("reverse", Value::Operation(Op::Reverse)),
+    ("builtins", Value::Operation(Op::Builtins)),
+    ("amin", Value::Operation(Op::AMin)),
+    ("amax", Value::Operation(Op::AMax)),
+    ("int", Value::Operation(Op::ToInt)),
+    ("bool", Value::Operation(Op::ToBool)),
+    ("sort", Value::Operation(Op::Sort)),
+    ("sort_desc", Value::Operation(Op::SortDesc)),
+    ("uniq", Value::Operation(Op::Uniq)),
+    ("dedup_all", Value::Operation(Op::DedupAll)),
+    ("scan", Value::Operation(Op::Scan)),
+    ("hex", Value::Operation(Op::Hex)),
+    ("bin", Value::Operation(Op::Bin)),
+    ("read_bytes", Value::Operation(Op::ReadBytes)),
+    ("to_bytes", Value::Operation(Op::ToBytes)),
+    ("from_bytes", Value::Operation(Op::FromBytes)),
+    ("is_empty", Value::Operation(Op::IsEmpty)),
+    ("to_array", Value::Operation(Op::ToArray)),
+    ("pad_left", Value::Operation(Op::PadLeft)),
+    ("pad_right", Value::Operation(Op::PadRight)),
+    ("clamp", Value::Operation(Op::Clamp)),
+    ("any", Value::Operation(Op::Any)),
+    ("all", Value::Operation(Op::All)),
+    ("set_all", Value::Operation(Op::SetAll)),
+    ("join", Value::Operation(Op::Join)),
+    ("nth", Value::Operation(Op::Nth)),
+    ("is_digit", Value::Operation(Op::IsDigit)),
+    ("is_alpha", Value::Operation(Op::IsAlpha)),
+    ("is_alnum", Value::Operation(Op::IsAlnum)),
+    ("is_space", Value::Operation(Op::IsSpace)),
+    ("gcd", Value::Operation(Op::Gcd)),
+    ("lcm", Value::Operation(Op::Lcm)),
+    ("modpow", Value::Operation(Op::ModPow)),
+    ("starts_with", Value::Operation(Op::StartsWith)),
+    ("ends_with", Value::Operation(Op::EndsWith)),
+    ("contains", Value::Operation(Op::Contains)),
+];
+
+/// fnv-1a, every ident that runs is looked up in the word table and the default hasher is slow for that
+#[derive(Default)]
+struct WordHasher(u64);
+
+impl Hasher for WordHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 ^ *b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// the op a built in word stands for, None for keywords and anything else
+fn word_op(name: &str) -> Option<&'static Op> {
+    type Words = hash_map::HashMap<&'static str, &'static Op, std::hash::BuildHasherDefault<WordHasher>>;
+    static OPS: OnceLock<Words> = OnceLock::new();
+    OPS.get_or_init(|| {
+        WORDS.iter()
+            .filter_map(|(w, v)| if let Value::Operation(op) = v { Some((*w, op)) } else { None })
+            .collect()
+    })
+    .get(name)
+    .copied()
+}
+
+/// the word a keyword or op is written as, for error messages
+fn word_name(v: &Value) -> &'static str {
+    if let Value::Operation(op) = v {
+        let sym = match op {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Assign => "=",
+            Op::Equal => "==",
+            Op::Less => "<",
+            Op::Greater => ">",
+            Op::LessEq => "<=",
+            Op::GreaterEq => ">=",
+            Op::AddAssign => "+=",
+            Op::SubAssign => "-=",
+            Op::MulAssign => "*=",
+            Op::DivAssign => "/=",
+            Op::Invert => "!",
+            Op::CallFn => "@",
+            Op::IndexArray => "#",
+            Op::Quote => "`",
+            Op::TupleStart | Op::TupleEnd | Op::BlockStart | Op::BlockEnd | Op::ArrayStart
+            | Op::ArrayEnd => return delim_str(op),
+            _ => "",
+        };
+        if !sym.is_empty() {
+            return sym;
+        }
+    }
+    WORDS.iter()
+        .find(|(_, w)| w == v)
+        .map(|(n, _)| *n)
+        .unwrap_or("?")
+}
+
+fn op_name(op: &Op) -> &'static str {
+    word_name(&Value::Operation(op.clone()))
+}
+
+fn keyword_name(kw: &Keyword) -> &'static str {
+    word_name(&Value::Keyword(kw.clone()))
+}
+
+/// how many values the group opening at vals[0] spans, its end included
+fn group_len(vals: &[Value], start: Op, end: Op) -> Option<usize> {
+    let mut depth = 0;
+    for (i, v) in vals.iter().enumerate() {
+        if let Value::Operation(op) = v {
+            if *op == start {
+                depth += 1;
+            } else if *op == end {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// whether an ident followed by `rest` is a name being bound or asked about, rather than a word to run.
+/// covers `name let`, `name global`, `name defined`, `name undef`, `old name rename`,
+/// `name ( args ) { body } def` and `iter name { body } for`
+fn binds_name(rest: &[Value]) -> bool {
+    let after_block = |at: usize| -> Option<&Value> {
+        if rest.get(at) != Some(&Value::Operation(Op::BlockStart)) {
+            return None;
+        }
+        group_len(&rest[at..], Op::BlockStart, Op::BlockEnd).and_then(|body| rest.get(at + body))
+    };
+    match rest.first() {
+        Some(Value::Keyword(Keyword::Let | Keyword::Global | Keyword::Defined | Keyword::Undef | Keyword::Rename)) => true,
+        Some(Value::Operation(Op::TupleStart)) => {
+            let kw = group_len(rest, Op::TupleStart, Op::TupleEnd).and_then(after_block);
+            kw == Some(&Value::Keyword(Keyword::Def))
+        }
+        Some(Value::Operation(Op::BlockStart)) => {
+            matches!(after_block(0), Some(Value::Keyword(Keyword::For | Keyword::ForCollect)))
+        }
+        _ => false,
+    }
+}
+
+/// the ops written with symbols rather than words
+const SYMBOLS: &[&str] = &[
+    "+", "-", "*", "/", "%", "=", "==", "<", ">", "<=", ">=", "!", "@", "#", "`", "(", ")", "{", "}", "[", "]",
+];
+
+/// like `Program::parse` without the delimiter check, bad input stops with a panic
+pub fn tokenize(fortnite: &str) -> Vec<Value> {
+    lex(fortnite).unwrap_or_else(|e| panic!("{}", e))
+}
+
+fn lex(fortnite: &str) -> Result<Vec<Value>, ProgramError> {
+    // a #! line at the very top is for the os, not for us. the newline stays
+    let fortnite = if fortnite.starts_with("#!") {
+        &fortnite[fortnite.find('\n').unwrap_or(fortnite.len())..]
+    } else {
+        fortnite
+    };
+    let mut cur_val = Value::None;
+    let mut cur_str = String::new();
+    let mut vals = vec![];
+    // r"..." strings take backslashes literally and end at the first quote
+    let mut raw = false;
+    let mut escaped = false;
+    // the trailing space flushes a token that runs up to the end of the input
+    for ch in fortnite.chars().chain(std::iter::once(' ')) {
+        match cur_val {
+            Value::None => {
+                if ch.is_numeric() {
+                    cur_val = Value::Int(0);
+                    cur_str.push(ch);
+                } else if ch.is_ascii_alphabetic() {
+                    cur_val = Value::Ident(String::new());
+                    cur_str.push(ch);
+                } else if ch == '"' {
+                    cur_val = Value::String(String::new());
+                    // cur_str.push(ch);
+                } else if ch == '`' {
+                    vals.push(Value::Operation(Op::Quote));
+                } else if matches!(ch, ' ' | '\n' | '\r' | '\t') {
+                    cur_str.clear();
+                } else {
+                    let op =
+                        match ch {
+                            '+' => {Op::Add}
+                            '-' => {Op::Sub}
+                            '*' => {Op::Mul}
+                            '/' => {Op::Div}
+                            '%' => {Op::Mod}
+                            '=' => {Op::Assign}
+                            '<' => {Op::Less}
+                            '>' => {Op::Greater}
+                            '!' => {Op::Invert}
+                            '(' => {Op::TupleStart}
+                            ')' => {Op::TupleEnd}
+                            '{' => {Op::BlockStart}
+                            '}' => {Op::BlockEnd}
+                            '[' => {Op::ArrayStart}
+                            ']' => {Op::ArrayEnd}
+                            '@' => {Op::CallFn}
+                            '#' => {Op::IndexArray}
+                            _ => {return Err(ProgramError::InvalidChar(ch))}
+                        };
+                    cur_val = Value::Operation(op);
+                }
+            }
+            Value::Int(_) => {
+                if !ch.is_numeric() {
+                    let Ok(i) = cur_str.parse() else {
+                        return Err(ProgramError::IntTooBig(cur_str));
+                    };
+                    vals.push(Value::Int(i));
+                    cur_str.clear();
+                    cur_val = Value::None;
+                    continue;
+                }
+                cur_str.push(ch);
+            }
+            Value::String(_) => {
+                if escaped {
+                    let esc = match ch {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        '0' => '\0',
+                        '\\' => '\\',
+                        '"' => '"',
+                        _ => return Err(ProgramError::InvalidEscape(ch)),
+                    };
+                    cur_str.push(esc);
+                    escaped = false;
+                    continue;
+                }
+                if ch == '\\' && !raw {
+                    escaped = true;
+                    continue;
+                }
+                if ch == '"' {
+                    vals.push(Value::String(cur_str.clone()));
+                    cur_str.clear();
+                    cur_val = Value::None;
+                    raw = false;
+                    continue;
+                }
+                cur_str.push(ch);
+            }
+            Value::Ident(_) => {
+                if ch == '"' && cur_str == "r" {
+                    cur_str.clear();
+                    cur_val = Value::String(String::new());
+                    raw = true;
+                    continue;
+                }
+                if !ch.is_alphanumeric() && ch != '_' {
+                    // word ops are left as idents, exec decides whether a var shadows them
+                    if let Some((_, v @ Value::Keyword(_))) = WORDS.iter().find(|(w, _)| *w == cur_str) {
+                        vals.push(v.clone());
+                    } else {
+                        vals.push(Value::Ident(cur_str.clone()));
+                    }
+                    cur_str.clear();
+                    cur_val = Value::None;
+                    continue;
+                }
+                cur_str.push(ch);
+            }
+            Value::Operation(ref cop) => {
+                let op = match ch {
+                    '=' => {
+                        match cop {
+                            Op::Add => {
+                                Op::AddAssign
+                            }
+                            Op::Sub => {
+                                Op::SubAssign
+                            }
+                            Op::Mul => {
+                                Op::MulAssign
+                            }
+                            Op::Div => {
+                                Op::DivAssign
+                            }
+                            Op::Assign => {
+                                Op::Equal
+                            }
+                            Op::Less => {
+                                Op::LessEq
+                            }
+                            Op::Greater => {
+                                Op::GreaterEq
+                            }
+                            _ => {
+                                return Err(ProgramError::InvalidOperator(format!("{}=", op_name(cop))));
+                            }
+                        }
+                    }
+                    _ => {
+                        vals.push(cur_val);
+                        cur_str.clear();
+                        cur_val = Value::None;
+                        continue;
+                    }
+                };
+                cur_val = Value::Operation(op);
+            }
+            _ => {}
+        }
+    }
+    // the flushing space above would otherwise just end up inside it
+    if let Value::String(_) = cur_val {
+        return Err(ProgramError::UnclosedString);
+    }
+    Ok(quote_ops(vals))
+}
+
+/// turns a backtick and the op after it into a fn taking the op's args, so `+ can be passed around
+fn quote_ops(vals: Vec<Value>) -> Vec<Value> {
+    let mut out = vec![];
+    let mut iter = vals.into_iter();
+    while let Some(val) = iter.next() {
+        if let Value::Operation(Op::Quote) = val {
+            let op = match iter.next() {
+                Some(Value::Operation(op)) => op,
+                Some(Value::Ident(name)) if word_op(&name).is_some() => word_op(&name).unwrap().clone(),
+                Some(v) => panic!("only ops can be quoted, got {}", v),
+                None => panic!("only ops can be quoted, got nothing"),
+            };
+            let Some((inputs, _)) = op_effect(&op) else {
+                panic!("cant quote {}", op_name(&op));
+            };
+            let args: Vec<String> = (0..inputs).map(|i| format!("arg{}", i)).collect();
+            let mut body: Vec<Value> = args.iter().cloned().map(Value::Ident).collect();
+            body.push(Value::Operation(op));
+            out.push(Value::Fn(Fn { args, body: body.into(), env: BTreeMap::new(), name: None }));
+        } else {
+            out.push(val);
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProgramError {
+    /// a closing delimiter with nothing (or the wrong thing) to close
+    Unmatched(Op),
+    /// a delimiter that is never closed
+    Unclosed(Op),
+    /// a string still open at the end of the input
+    UnclosedString,
+    InvalidChar(char),
+    /// a backslash followed by something that isnt an escape
+    InvalidEscape(char),
+    /// a symbol followed by = that doesnt make an op, like !=
+    InvalidOperator(String),
+    IntTooBig(String),
+}
+
+impl Display for ProgramError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProgramError::Unmatched(op) => {
+                write!(f, "unmatched {}", delim_str(op))
+            }
+            ProgramError::Unclosed(op) => {
+                write!(f, "unclosed {}", delim_str(op))
+            }
+            ProgramError::UnclosedString => {
+                write!(f, "unclosed string at the end of the input")
+            }
+            ProgramError::InvalidChar(ch) => {
+                write!(f, "invalid char {}", ch)
+            }
+            ProgramError::InvalidEscape(ch) => {
+                write!(f, "invalid escape \\{}", ch)
+            }
+            ProgramError::InvalidOperator(s) => {
+                write!(f, "invalid operator {}", s)
+            }
+            ProgramError::IntTooBig(s) => {
+                write!(f, "{} is too big for an int", s)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+fn delim_str(op: &Op) -> &'static str {
+    match op {
+        Op::TupleStart => "(",
+        Op::TupleEnd => ")",
+        Op::BlockStart => "{",
+        Op::BlockEnd => "}",
+        Op::ArrayStart => "[",
+        _ => "]",
+    }
+}
+
+/// a tokenized program that can be run many times without re-tokenizing
+#[derive(Debug, Clone)]
+pub struct Program {
+    vals: Vec<Value>,
+}
+
+impl Program {
+    pub fn parse(src: &str) -> Result<Program, ProgramError> {
+        Program::from_values(lex(src)?)
+    }
+    pub fn from_values(vals: Vec<Value>) -> Result<Program, ProgramError> {
+        let mut open = vec![];
+        for val in &vals {
+            if let Value::Operation(op) = val {
+                match op {
+                    Op::TupleStart | Op::BlockStart | Op::ArrayStart => {
+                        open.push(op.clone());
+                    }
+                    Op::TupleEnd | Op::BlockEnd | Op::ArrayEnd => {
+                        let start = match op {
+                            Op::TupleEnd => Op::TupleStart,
+                            Op::BlockEnd => Op::BlockStart,
+                            _ => Op::ArrayStart,
+                        };
+                        if open.pop() != Some(start) {
+                            return Err(ProgramError::Unmatched(op.clone()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if let Some(op) = open.pop() {
+            return Err(ProgramError::Unclosed(op));
+        }
+        Ok(Program { vals })
+    }
+    pub fn values(&self) -> &[Value] {
+        &self.vals
+    }
+    pub fn run(&self, state: &mut InterpreterState) -> Flow {
+        state.run(&self.vals)
+    }
+}
+
+/// how many values a snippet takes off the stack and how many it leaves
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackEffect {
+    pub consumes: usize,
+    pub produces: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnalysisError {
+    Delims(ProgramError),
+    /// the value at this index has an effect that depends on runtime values (fn calls, eval, pack, ...)
+    Unknown(usize, Value),
+}
+
+impl Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AnalysisError::Delims(e) => {
+                write!(f, "{}", e)
+            }
+            AnalysisError::Unknown(i, v) => {
+                write!(f, "cant tell the stack effect of {} at {}", v, i)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+/// the (inputs, outputs) of a single op, None when it depends on runtime values
+fn op_effect(op: &Op) -> Option<(usize, usize)> {
+    let effect = match op {
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Min | Op::Max | Op::Equal
+        | Op::Less | Op::Greater | Op::LessEq | Op::GreaterEq
+        | Op::OrElse | Op::WriteFile | Op::MinBy | Op::MaxBy | Op::Range | Op::Take
+        | Op::Skip | Op::Random | Op::IndexArray | Op::PadLeft | Op::PadRight
+        | Op::Any | Op::All | Op::Join | Op::Nth | Op::Gcd | Op::Lcm
+        | Op::StartsWith | Op::EndsWith | Op::Contains => (2, 1),
+        Op::Abs | Op::Reverse | Op::Copy | Op::IsNone | Op::ReadFile | Op::Sum | Op::Product
+        | Op::Count | Op::Enumerate | Op::ConcatAll | Op::First | Op::Last | Op::Flatten
+        | Op::Chars | Op::Bytes | Op::Invert | Op::Upper | Op::Lower | Op::Trim
+        | Op::AMin | Op::AMax | Op::ToInt | Op::ToBool | Op::Sort | Op::SortDesc
+        | Op::Uniq | Op::DedupAll | Op::Hex | Op::Bin
+        | Op::ReadBytes | Op::ToBytes | Op::FromBytes | Op::IsEmpty
+        | Op::ToArray | Op::IsDigit | Op::IsAlpha | Op::IsAlnum | Op::IsSpace => (1, 1),
+        Op::RangeStep | Op::Scan | Op::Clamp | Op::ModPow => (3, 1),
+        Op::Now | Op::CallDepth | Op::Builtins => (0, 1),
+        Op::Rot | Op::RRot => (3, 3),
+        Op::Tuck => (2, 3),
+        Op::Dup2 => (2, 4),
+        Op::Assign => (2, 0),
+        Op::AssertStack => (1, 0),
+        Op::Splice => (4, 0),
+        Op::Partition => (2, 2),
+        Op::AddAssign | Op::SubAssign | Op::MulAssign | Op::DivAssign => (0, 0),
+        // a whole delimited group counts as one value, see stack_effect
+        Op::TupleStart | Op::TupleEnd | Op::BlockStart | Op::BlockEnd
+        | Op::ArrayStart | Op::ArrayEnd => (0, 1),
+        Op::Pack | Op::Unpack | Op::CallFn | Op::Quote | Op::SetAll => return None,
+    };
+    Some(effect)
+}
+
+fn keyword_effect(kw: &Keyword) -> Option<(usize, usize)> {
+    // blocks run with their own stack, so for/if/loop bodies dont count
+    let effect = match kw {
+        Keyword::Let | Keyword::Global | Keyword::Defined | Keyword::Undef | Keyword::Capture => (1, 1),
+        Keyword::Print | Keyword::PrintLn | Keyword::Loop | Keyword::Exit | Keyword::Defer => (1, 0),
+        Keyword::Fn => (2, 1),
+        Keyword::For | Keyword::Def => (3, 0),
+        Keyword::ForCollect => (3, 1),
+        Keyword::If | Keyword::PrintJoined | Keyword::Times | Keyword::SwapVars
+        | Keyword::Rename => (2, 0),
+        Keyword::Break | Keyword::PrintStack => (0, 0),
+        Keyword::Eval | Keyword::PrintN => return None,
+    };
+    Some(effect)
+}
+
+/// works out the net stack effect of straight-line code without running it.
+/// anything whose effect depends on runtime values is reported as unknown
+pub fn stack_effect(vals: &[Value]) -> Result<StackEffect, AnalysisError> {
+    let program = Program::from_values(vals.to_vec()).map_err(AnalysisError::Delims)?;
+    let mut depth: isize = 0;
+    let mut consumes: isize = 0;
+    let mut nesting = 0;
+    for (i, val) in program.values().iter().enumerate() {
+        if let Value::Operation(Op::TupleStart | Op::BlockStart | Op::ArrayStart) = val {
+            nesting += 1;
+            if nesting > 1 {
+                continue;
+            }
+        } else if let Value::Operation(Op::TupleEnd | Op::BlockEnd | Op::ArrayEnd) = val {
+            // the group was already counted when it opened
+            nesting -= 1;
+            continue;
+        } else if nesting > 0 {
+            continue;
+        }
+        let effect = match val {
+            Value::Operation(op) => op_effect(op),
+            Value::Keyword(kw) => keyword_effect(kw),
+            // taken to be the word op, a var shadowing it can only be known at run time
+            Value::Ident(name) if !binds_name(&program.values()[i + 1..]) => {
+                word_op(name).map_or(Some((0, 1)), op_effect)
+            }
+            _ => Some((0, 1)),
+        };
+        let Some((inputs, outputs)) = effect else {
+            return Err(AnalysisError::Unknown(i, val.clone()));
+        };
+        depth -= inputs as isize;
+        consumes = consumes.max(-depth);
+        depth += outputs as isize;
+    }
+    Ok(StackEffect {
+        consumes: consumes as usize,
+        produces: (depth + consumes) as usize,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// runs a script with the given ext fns, giving back what it printed, what it left on the stack and how it ended
+    fn run_with(ext_fns: &ExtFns, src: &str) -> (String, Vec<Value>, Flow) {
+        let mut out = Vec::new();
+        let mut istate = InterpreterState::with_output(ext_fns, Box::new(&mut out));
+        let flow = istate.run(&tokenize(src));
+        let stack = std::mem::take(&mut istate.stack);
+        drop(istate);
+        (String::from_utf8(out).unwrap(), stack, flow)
+    }
+
+    fn run(src: &str) -> (String, Vec<Value>, Flow) {
+        run_with(&ExtFns::new(), src)
+    }
+
+    /// how a program that never ends on its own finishes with a short timeout
+    fn timed_out(src: &str) -> Flow {
+        let ext_fns = ExtFns::new();
+        let mut out = Vec::new();
+        let mut istate = InterpreterState::with_output(&ext_fns, Box::new(&mut out));
+        istate.set_timeout(std::time::Duration::from_millis(20));
+        istate.run(&tokenize(src))
+    }
+
+    fn printed(src: &str) -> String {
+        run(src).0
+    }
+
+    fn ints(is: &[i32]) -> Vec<Value> {
+        is.iter().map(|i| Value::Int(*i)).collect()
+    }
+
+    #[test]
+    fn chained_index() {
+        let src = "m let [ [ 1 2 ] [ 3 4 ] ] = m 1 # 0 # m 0 # 1 #";
+        assert_eq!(run(src).1, ints(&[3, 2]));
+    }
+
+    #[test]
+    fn chained_index_three_deep() {
+        let src = "m let [ [ [ 1 2 ] [ 3 4 ] ] [ [ 5 6 ] [ 7 8 ] ] ] = m 1 # 0 # 1 #";
+        assert_eq!(run(src).1, ints(&[6]));
+    }
+
+    #[test]
+    fn index_gives_inner_array_intact() {
+        let src = "[ [ 1 2 ] [ 3 4 ] ] 1 #";
+        assert_eq!(run(src).1, vec![Value::Array(Arc::new(ints(&[3, 4])))]);
+    }
+
+    #[test]
+    fn chained_index_on_a_string_element() {
+        assert_eq!(printed("[ \"ab\" \"cd\" ] 1 # 0 # println"), "c\n");
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn chained_index_out_of_range() {
+        run("[ [ 1 2 ] [ 3 4 ] ] 1 # 2 #");
+    }
+
+    fn range(src: &str) -> Vec<Value> {
+        match run(&format!("{} to_array", src)).1.as_slice() {
+            [Value::Array(a)] => a.to_vec(),
+            st => panic!("expected one array, got {:?}", st),
+        }
+    }
+
+    #[test]
+    fn range_bounds() {
+        assert_eq!(range("0 5 range"), ints(&[0, 1, 2, 3, 4]));
+        assert_eq!(range("5 5 range"), ints(&[]));
+        assert_eq!(range("5 6 range"), ints(&[5]));
+        assert_eq!(range("6 5 range"), ints(&[6]));
+    }
+
+    #[test]
+    fn range_counts_down() {
+        assert_eq!(range("10 5 range"), ints(&[10, 9, 8, 7, 6]));
+        assert_eq!(range("0 0 3 - range"), ints(&[0, -1, -2]));
+    }
+
+    #[test]
+    fn range_step() {
+        assert_eq!(range("0 10 3 range_step"), ints(&[0, 3, 6, 9]));
+        assert_eq!(range("10 0 0 3 - range_step"), ints(&[10, 7, 4, 1]));
+        assert_eq!(range("5 5 2 range_step"), ints(&[]));
+        // a step going the wrong way never gets anywhere
+        assert_eq!(range("0 10 0 1 - range_step"), ints(&[]));
+        assert_eq!(range("10 0 2 range_step"), ints(&[]));
+    }
+
+    #[test]
+    fn range_near_int_max() {
+        assert_eq!(range("2147483645 2147483647 range"), ints(&[2147483645, 2147483646]));
+    }
+
+    #[test]
+    #[should_panic(expected = "range step cant be 0")]
+    fn range_step_zero() {
+        run("0 10 0 range_step");
+    }
+
+    #[test]
+    fn for_walks_a_range() {
+        assert_eq!(printed("3 0 range i { i print } for"), "321");
+        assert_eq!(printed("5 5 range i { i print } for"), "");
+    }
+
+    #[test]
+    fn call_binds_args_in_push_order() {
+        let src = "f let ( a b ) { a b - } fn = 10 3 f @";
+        assert_eq!(run(src).1, ints(&[7]));
+        let src = "f let ( a b c ) { a print b print c print } fn = 1 2 3 f @";
+        assert_eq!(printed(src), "123");
+    }
+
+    #[test]
+    fn call_only_takes_its_args() {
+        let src = "f let ( a ) { a 2 * } fn = 1 2 3 f @";
+        assert_eq!(run(src).1, ints(&[1, 2, 6]));
+    }
+
+    #[test]
+    fn call_returns_everything_left() {
+        let src = "divmod let ( a b ) { a b / a b % } fn = 7 2 divmod @";
+        assert_eq!(run(src).1, ints(&[3, 1]));
+    }
+
+    #[test]
+    fn quoted_op_keeps_arg_order() {
+        let src = "s let `- = 10 3 s @";
+        assert_eq!(run(src).1, ints(&[7]));
+    }
+
+    #[test]
+    fn vars_shadow_word_ops() {
+        assert_eq!(printed("count let 0 = count count 1 + = count println"), "1\n");
+        assert_eq!(printed("( first last ) let ( 1 2 ) = first last + println"), "3\n");
+        assert_eq!(printed("max global 5 = max println"), "5\n");
+        assert_eq!(printed("x let 1 = x min rename min println"), "1\n");
+        // without a binding the word still runs
+        assert_eq!(printed("[ 1 2 3 ] count println"), "3\n");
+        assert_eq!(printed("now defined println"), "0\n");
+    }
+
+    #[test]
+    fn def_and_args_shadow_word_ops() {
+        assert_eq!(printed("sum ( xs ) { 99 } def [ 1 2 ] sum @ println"), "99\n");
+        assert_eq!(printed("f let ( first ) { first 1 + } fn = 4 f @ println"), "5\n");
+        assert_eq!(printed("[ 1 2 ] first { first println } for"), "1\n2\n");
+    }
+
+    #[test]
+    fn shadowing_is_scoped() {
+        let src = "f let ( ) { count let 5 = count } fn = f @ [ 1 2 ] count";
+        assert_eq!(run(src).1, ints(&[5, 2]));
+    }
+
+    #[test]
+    fn ext_fns_shadow_word_ops() {
+        let ext_fns = with_ext_fns([("first", (|_| Value::Int(42)) as fn(Value) -> Value)]);
+        assert_eq!(run_with(&ext_fns, "[ 1 2 ] first @").1, ints(&[42]));
+    }
+
+    #[test]
+    fn quoted_word_op_ignores_shadowing() {
+        assert_eq!(run("min let 0 = 2 3 `min @").1, ints(&[2]));
+    }
+
+    #[test]
+    #[should_panic(expected = "print is a keyword, it cant be used as a name")]
+    fn keyword_as_name() {
+        run("print let 1 =");
+    }
+
+    #[test]
+    fn stack_effect_counts_word_ops() {
+        let effect = stack_effect(&tokenize("count 1 +")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 1, produces: 1 });
+        let effect = stack_effect(&tokenize("count let")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 0, produces: 1 });
+    }
+
+    #[test]
+    fn stack_effect_counts_a_group_as_one_value() {
+        let effect = stack_effect(&tokenize("[ 1 [ 2 3 ] ( 4 + ) { 5 print } ]")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 0, produces: 1 });
+        let effect = stack_effect(&tokenize("( a b ) { a b + } fn")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 0, produces: 1 });
+    }
+
+    #[test]
+    fn stack_effect_of_if() {
+        // the condition block runs on its own stack like the body
+        let effect = stack_effect(&tokenize("{ x 1 == } { 2 println } if")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 0, produces: 0 });
+        let effect = stack_effect(&tokenize("{ 2 println } if")).unwrap();
+        assert_eq!(effect, StackEffect { consumes: 1, produces: 0 });
+    }
+
+    #[test]
+    fn stack_effect_unknown() {
+        let err = stack_effect(&tokenize("1 f @")).unwrap_err();
+        assert_eq!(err, AnalysisError::Unknown(2, Value::Operation(Op::CallFn)));
+        let err = stack_effect(&tokenize("1 2 2 pack")).unwrap_err();
+        assert_eq!(err, AnalysisError::Unknown(3, Value::Ident("pack".to_string())));
+        let err = stack_effect(&tokenize("\"1\" eval")).unwrap_err();
+        assert_eq!(err, AnalysisError::Unknown(1, Value::Keyword(Keyword::Eval)));
+    }
+
+    #[test]
+    fn stack_effect_of_bad_delims() {
+        let err = stack_effect(&tokenize("1 ]")).unwrap_err();
+        assert_eq!(err, AnalysisError::Delims(ProgramError::Unmatched(Op::ArrayEnd)));
+        let err = stack_effect(&tokenize("{ 1")).unwrap_err();
+        assert_eq!(err, AnalysisError::Delims(ProgramError::Unclosed(Op::BlockStart)));
+    }
+
+    #[test]
+    fn empty_programs() {
+        for src in ["", " ", "\n\n", " \t\r\n ", "#!/usr/bin/env knusper", "#!/usr/bin/env knusper\n\n"] {
+            assert_eq!(tokenize(src), vec![], "{:?}", src);
+            assert_eq!(run(src), (String::new(), vec![], Flow::Normal), "{:?}", src);
+            assert!(Program::parse(src).is_ok(), "{:?}", src);
+        }
+    }
+
+    #[test]
+    fn last_token_is_flushed() {
+        assert_eq!(tokenize("12"), ints(&[12]));
+        assert_eq!(tokenize("x"), vec![Value::Ident("x".to_string())]);
+        assert_eq!(tokenize("\"a b\""), vec![Value::String("a b".to_string())]);
+        assert_eq!(tokenize("1 2 =="), vec![Value::Int(1), Value::Int(2), Value::Operation(Op::Equal)]);
+        assert_eq!(tokenize("println"), vec![Value::Keyword(Keyword::PrintLn)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unclosed string")]
+    fn unclosed_string_at_end() {
+        tokenize("\"hi\" println \"abc");
+    }
+
+    #[test]
+    #[should_panic(expected = "unclosed string")]
+    fn unclosed_raw_string_at_end() {
+        tokenize("r\"abc");
+    }
+
+    #[test]
+    fn bad_input_is_a_parse_error() {
+        assert_eq!(Program::parse("\"abc").unwrap_err(), ProgramError::UnclosedString);
+        assert_eq!(Program::parse("r\"abc").unwrap_err(), ProgramError::UnclosedString);
+        assert_eq!(Program::parse("1 $").unwrap_err(), ProgramError::InvalidChar('$'));
+        assert_eq!(Program::parse("\"a\\q\"").unwrap_err(), ProgramError::InvalidEscape('q'));
+        assert_eq!(Program::parse("1 2 !=").unwrap_err(), ProgramError::InvalidOperator("!=".to_string()));
+        assert_eq!(Program::parse("99999999999").unwrap_err(), ProgramError::IntTooBig("99999999999".to_string()));
+        assert_eq!(Program::parse("\"abc").unwrap_err().to_string(), "unclosed string at the end of the input");
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid char $")]
+    fn tokenize_panics_on_an_invalid_char() {
+        tokenize("1 $");
+    }
+
+    #[test]
+    fn recursion_stays_within_limits() {
+        let src = "f ( n ) { n { n 1 - f @ } if } def 5 f @ call_depth";
+        assert_eq!(run(src).1, ints(&[0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "recursion limit")]
+    fn deep_recursion_stops_before_the_stack_overflows() {
+        // tests run on 2mb threads, which a debug build overflows well before DEFAULT_MAX_DEPTH
+        run("f ( n ) { n { n 1 - f @ } if } def 100000 f @");
+    }
+
+    #[test]
+    #[should_panic(expected = "recursion limit of 4 reached")]
+    fn recursion_limit_can_be_lowered() {
+        let ext_fns = ExtFns::new();
+        let mut istate = InterpreterState::with_output(&ext_fns, Box::new(io::sink()));
+        istate.set_recursion_limit(4);
+        istate.run(&tokenize("f ( n ) { n { n 1 - f @ } if } def 10 f @"));
+    }
+
+    /// what the joe ext fn gives back for v
+    fn joe(v: Value) -> Value {
+        Value::Tuple(vec![Value::String("joe".to_string()), v])
+    }
+
+    fn joe_ext_fns() -> ExtFns {
+        with_ext_fns([("joe", joe as fn(Value) -> Value)])
+    }
+
+    #[test]
+    fn ext_fn_is_called_when_nothing_shadows_it() {
+        assert_eq!(run_with(&joe_ext_fns(), "1 joe @").1, vec![joe(Value::Int(1))]);
+        // stored in a var like any other fn
+        assert_eq!(run_with(&joe_ext_fns(), "f let joe = 2 f @").1, vec![joe(Value::Int(2))]);
+    }
+
+    #[test]
+    fn var_shadows_ext_fn() {
+        let src = "joe let ( x ) { x 2 * } fn = 4 joe @";
+        assert_eq!(run_with(&joe_ext_fns(), src).1, ints(&[8]));
+        let src = "joe global ( x ) { x 3 * } fn = 4 joe @";
+        assert_eq!(run_with(&joe_ext_fns(), src).1, ints(&[12]));
+    }
+
+    #[test]
+    fn shadowing_ends_with_the_var() {
+        let src = "joe let 5 = joe undef 1 joe @";
+        assert_eq!(run_with(&joe_ext_fns(), src).1, vec![Value::Int(1), joe(Value::Int(1))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cant call joe: the variable joe = 5 hides the ext fn of the same name")]
+    fn calling_a_var_that_shadows_an_ext_fn() {
+        run_with(&joe_ext_fns(), "joe let 5 = joe @");
+    }
+
+    #[test]
+    fn one_program_on_several_threads() {
+        let ext_fns = joe_ext_fns();
+        let program = Program::parse("x let 0 = [ 1 2 3 ] i { x x i + = } for x n * joe @").unwrap();
+        let results: Vec<Vec<Value>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (1..=4).map(|n| {
+                let (ext_fns, program) = (&ext_fns, &program);
+                s.spawn(move || {
+                    let mut istate = InterpreterState::with_output(ext_fns, Box::new(io::sink()));
+                    // each state has its own vars and globals, so the runs cant see each other
+                    istate.globals.insert("n".to_string(), Value::Int(n));
+                    assert_eq!(program.run(&mut istate), Flow::Normal);
+                    istate.stack
+                })
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for (n, stack) in (1..=4).zip(results) {
+            assert_eq!(stack, vec![joe(Value::Int(6 * n))]);
+        }
+    }
+
+    #[test]
+    fn state_moves_to_another_thread() {
+        let ext_fns = ExtFns::new();
+        let mut istate = InterpreterState::with_output(&ext_fns, Box::new(io::sink()));
+        istate.run(&tokenize("x global 1 ="));
+        let x = std::thread::scope(|s| {
+            s.spawn(move || {
+                istate.run(&tokenize("x x 1 + ="));
+                istate.get_global("x").cloned()
+            }).join().unwrap()
+        });
+        assert_eq!(x, Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn ordering_ops() {
+        assert_eq!(run("1 2 < 2 1 < 2 2 < 2 2 <= 3 2 > 2 3 >= 3 3 >=").1, ints(&[1, 0, 0, 1, 1, 0, 1]));
+        assert_eq!(run("\"apple\" \"pear\" < \"b\" first \"a\" first >").1, ints(&[1, 1]));
+    }
+
+    #[test]
+    fn ordering_ops_in_infix() {
+        let ext_fns = ExtFns::new();
+        let mut istate = InterpreterState::with_output(&ext_fns, Box::new(io::sink()));
+        istate.run(&prefix::lower("1 + 1 < 3; 2 >= 3 - 1; 4 > 5").unwrap());
+        assert_eq!(istate.stack, ints(&[1, 1, 0]));
+    }
+
+    fn lowers_to(infix: &str, stack: &str) {
+        assert_eq!(prefix::lower(infix).unwrap(), tokenize(stack), "{}", infix);
+    }
+
+    fn printed_infix(src: &str) -> String {
+        let ext_fns = ExtFns::new();
+        let mut out = Vec::new();
+        InterpreterState::with_output(&ext_fns, Box::new(&mut out)).run(&prefix::lower(src).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn infix_precedence() {
+        lowers_to("1 + 2 * 3", "1 2 3 * +");
+        lowers_to("(1 + 2) * 3", "1 2 + 3 *");
+        lowers_to("1 - 2 - 3", "1 2 - 3 -");
+        lowers_to("8 / 4 / 2 % 3", "8 4 / 2 / 3 %");
+        lowers_to("1 + 2 == 3", "1 2 + 3 ==");
+    }
+
+    #[test]
+    fn infix_unary_minus() {
+        lowers_to("-x * 2", "0 x - 2 *");
+        lowers_to("--1", "0 0 1 - -");
+        lowers_to("3 - -1", "3 0 1 - -");
+    }
+
+    #[test]
+    fn infix_let_and_assign() {
+        lowers_to("let x = 1 + 2", "x let 1 2 + =");
+        lowers_to("x = x * 2; x", "x x 2 * = x");
+        assert_eq!(printed_infix("let x = 2\nx = x + 5\nprintln(x)"), "7\n");
+    }
+
+    #[test]
+    fn infix_calls() {
+        lowers_to("f(2)", "2 f @");
+        lowers_to("f(1, g(2))", "1 2 g @ f @");
+        lowers_to("f()", "f @");
+        lowers_to("println(1, 2)", "1 2 println");
+        // call form is the word even where a bare name would be looked up first
+        assert_eq!(prefix::lower("sum(xs)").unwrap(), vec![Value::Ident("xs".to_string()), Value::Operation(Op::Sum)]);
+        lowers_to("sum", "sum");
+        assert_eq!(printed_infix("let count = \"abc\"\nprintln(count(count))"), "3\n");
+    }
+
+    #[test]
+    fn infix_errors() {
+        let err = |src| prefix::lower(src).unwrap_err();
+        assert_eq!(err("1 +"), prefix::SyntaxError { pos: 3, msg: "expected a value".to_string() });
+        assert_eq!(err("println(\"ab"), prefix::SyntaxError { pos: 8, msg: "unclosed string".to_string() });
+        assert_eq!(err("1 2"), prefix::SyntaxError { pos: 2, msg: "expected the end of the statement".to_string() });
+        assert_eq!(err("let = 1"), prefix::SyntaxError { pos: 4, msg: "expected a name after let".to_string() });
+        assert_eq!(err("f(1 2)"), prefix::SyntaxError { pos: 4, msg: "expected ,".to_string() });
+        assert_eq!(err("1 $").to_string(), "unexpected '$' at 2");
+    }
+
+    #[test]
+    fn tuples_compare_field_by_field() {
+        assert_eq!(run("( 1 2 ) ( 1 3 ) < ( 2 0 ) ( 1 9 ) > ( 1 2 ) ( 1 2 ) <=").1, ints(&[1, 1, 1]));
+        // a shorter prefix comes first
+        assert_eq!(run("( 1 ) ( 1 0 ) < ( 1 \"b\" ) ( 1 \"a\" ) >").1, ints(&[1, 1]));
+        assert_eq!(run("( 1 2 ) ( 1 2 ) == ( 1 2 ) ( 2 1 ) ==").1, ints(&[1, 0]));
+    }
+
+    #[test]
+    fn sort_tuples() {
+        let src = "[ ( 3 \"c\" ) ( 1 \"z\" ) ( 1 \"a\" ) ] sort";
+        let sorted = Value::Array(Arc::new(vec![
+            Value::Tuple(vec![Value::Int(1), Value::String("a".to_string())]),
+            Value::Tuple(vec![Value::Int(1), Value::String("z".to_string())]),
+            Value::Tuple(vec![Value::Int(3), Value::String("c".to_string())]),
+        ]));
+        assert_eq!(run(src).1, vec![sorted]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cant compare")]
+    fn comparing_different_kinds() {
+        run("( 1 2 ) ( \"a\" 2 ) <");
+    }
+
+    #[test]
+    fn rename_in_a_child_scope() {
+        let src = "a let 1 = b let 2 = 1 { a c rename } if c println a defined println";
+        assert_eq!(printed(src), "1\n0\n");
+        let src = "a let 5 = 1 { 0 1 range i { 1 { a c rename } if } for } times c println";
+        assert_eq!(printed(src), "5\n");
+        let src = "a global 3 = 1 { a c rename } if c println";
+        assert_eq!(printed(src), "3\n");
+    }
+
+    #[test]
+    fn rename_in_a_fn_stays_in_the_fn() {
+        let src = "f let ( a ) { a c rename c } fn = a let 7 = a f @ a println";
+        assert_eq!(run(src), ("7\n".to_string(), ints(&[7]), Flow::Normal));
+    }
+
+    #[test]
+    fn swap_vars_in_a_child_scope() {
+        assert_eq!(printed("a let 1 = b let 2 = 1 { a b swap_vars } if a print b print"), "21");
+    }
+
+    #[test]
+    fn arrays_act_like_copies() {
+        let src = "a let [ 1 2 3 ] = b let a = a 0 1 [ 9 ] splice b println a println a reverse b println";
+        assert_eq!(printed(src), "[\n\t1 2 3\n]\n[\n\t9 2 3\n]\n[\n\t1 2 3\n]\n");
+    }
+
+    #[test]
+    fn fn_args_share_the_array() {
+        let src = "f let ( xs ) { xs 0 0 [ 0 ] splice xs } fn = a let [ 1 2 ] = a f @ a copy";
+        let (_, stack, _) = run(src);
+        assert_eq!(stack, vec![Value::Array(Arc::new(ints(&[0, 1, 2]))), Value::Array(Arc::new(ints(&[1, 2])))]);
+        // passing it in doesnt copy the elements
+        let src = "f let ( xs ) { xs } fn = a let [ 1 2 ] = a f @ a copy";
+        let (_, stack, _) = run(src);
+        let [Value::Array(x), Value::Array(y)] = stack.as_slice() else { panic!("expected two arrays, got {:?}", stack) };
+        assert!(Arc::ptr_eq(x, y));
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: + needs 2 values, the stack has 0")]
+    fn underflow_names_the_symbol() {
+        run("+");
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: rot needs 3 values, the stack has 2")]
+    fn underflow_names_the_word() {
+        run("1 2 rot");
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: println needs 1 value, the stack has 0")]
+    fn underflow_names_the_keyword() {
+        run("println");
+    }
+
+    #[test]
+    #[should_panic(expected = "stack underflow: f needs 2 args, the stack has 1")]
+    fn underflow_in_a_call() {
+        run("f let ( a b ) { a b + } fn = 1 f @");
+    }
+
+    #[test]
+    #[should_panic(expected = "any fn must give an int")]
+    fn messages_use_the_word() {
+        run("[ 1 ] ( x ) { \"a\" } fn any");
+    }
+
+    #[test]
+    #[should_panic(expected = "swap_vars needs two idents")]
+    fn keyword_messages_use_the_word() {
+        run("1 2 swap_vars");
+    }
+
+    #[test]
+    fn timeout_inside_callbacks() {
+        for word in ["any", "all", "min_by", "max_by", "partition"] {
+            let src = format!("[ 1 2 3 ] ( x ) {{ {{ }} loop 1 }} fn {} \"after\" println", word);
+            assert_eq!(timed_out(&src), Flow::Timeout, "{}", word);
+        }
+        assert_eq!(timed_out("[ 1 2 ] 0 ( a x ) { { } loop a } fn scan"), Flow::Timeout);
+    }
+
+    #[test]
+    fn timeout_inside_literals_and_calls() {
+        assert_eq!(timed_out("[ 1 { } loop ] println"), Flow::Timeout);
+        assert_eq!(timed_out("( 1 { } loop ) println"), Flow::Timeout);
+        assert_eq!(timed_out("f let ( ) { { } loop } fn = f @ 1 println"), Flow::Timeout);
+        assert_eq!(timed_out("{ { } loop 1 } { } if"), Flow::Timeout);
+        assert_eq!(timed_out("{ { } loop } defer"), Flow::Timeout);
+    }
+
+    #[test]
+    #[should_panic(expected = "abs of -2147483648 doesnt fit in an int")]
+    fn abs_of_the_smallest_int() {
+        run("0 2147483647 - 1 - abs");
+    }
+
+    #[test]
+    #[should_panic(expected = "sum of the array doesnt fit in an int")]
+    fn sum_overflow() {
+        run("[ 2147483647 1 ] sum");
+    }
+
+    #[test]
+    #[should_panic(expected = "doesnt fit in an int")]
+    fn product_overflow() {
+        run("[ 65536 65536 ] product");
+    }
+
+    #[test]
+    fn literals_copy_their_vars() {
+        assert_eq!(printed("x let 3 = y let [ x 1 ] = x 10 = y sum println x undef y sum println"), "4\n4\n");
+        assert_eq!(printed("x let 3 = [ x 1 ] i { i println } for"), "3\n1\n");
+        assert_eq!(printed("x let 3 = y let ( x 1 ) = x 10 = y println"), "(3 1)\n");
+        let src = "x let 3 = [ [ x ] ( x 2 ) ] enumerate \" \" print_joined";
+        assert_eq!(printed(src), "(0 [\n\t3\n]) (1 (3 2))");
+    }
+
+    #[test]
+    fn fn_returns_two_results_through_pack() {
+        let src = "f let ( a b ) { b a 2 pack } fn = 1 2 f @ unpack - println";
+        assert_eq!(printed(src), "1\n");
+        let src = "divmod let ( a b ) { a b / a b % 2 pack } fn = 17 5 divmod @ unpack println println";
+        assert_eq!(printed(src), "2\n3\n");
+    }
+
+    #[test]
+    fn fn_returns_a_tuple_of_its_names() {
+        assert_eq!(run("f let ( a b ) { ( b a ) } fn = 1 2 f @").1, vec![Value::Tuple(ints(&[2, 1]))]);
+    }
+
+    fn arrays(rows: &[&[i32]]) -> Value {
+        Value::Array(Arc::new(rows.iter().map(|r| Value::Array(Arc::new(ints(r)))).collect()))
+    }
+
+    #[test]
+    fn arith_on_two_arrays() {
+        assert_eq!(run("[ 1 2 3 ] [ 10 20 30 ] +").1, vec![Value::Array(Arc::new(ints(&[11, 22, 33])))]);
+        assert_eq!(run("[ 10 20 ] [ 3 4 ] %").1, vec![Value::Array(Arc::new(ints(&[1, 0])))]);
+    }
+
+    #[test]
+    fn arith_broadcasts_a_plain_int() {
+        assert_eq!(run("[ 1 2 3 ] 2 *").1, vec![Value::Array(Arc::new(ints(&[2, 4, 6])))]);
+        // the int stays on its side, so 10 - each and not each - 10
+        assert_eq!(run("10 [ 1 2 ] -").1, vec![Value::Array(Arc::new(ints(&[9, 8])))]);
+        assert_eq!(run("x let 3 = [ 6 9 ] x /").1, vec![Value::Array(Arc::new(ints(&[2, 3])))]);
+    }
+
+    #[test]
+    fn arith_on_nested_arrays() {
+        assert_eq!(run("[ [ 1 2 ] [ 3 4 ] ] 1 +").1, vec![arrays(&[&[2, 3], &[4, 5]])]);
+        assert_eq!(run("[ [ 1 2 ] [ 3 4 ] ] [ 10 100 ] *").1, vec![arrays(&[&[10, 20], &[300, 400]])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cant + arrays of 2 and 3 elements")]
+    fn arith_length_mismatch() {
+        run("[ 1 2 ] [ 1 2 3 ] +");
+    }
+
+    #[test]
+    #[should_panic(expected = "2 values left on the stack at the end, the top one is 7")]
+    fn strict_names_the_leftovers() {
+        let ext_fns = ExtFns::new();
+        let mut istate = InterpreterState::with_output(&ext_fns, Box::new(io::sink()));
+        istate.strict = true;
+        istate.run(&tokenize("1 7"));
+    }
+
+    #[test]
+    #[should_panic(expected = "only ops can be quoted, got 1")]
+    fn quoting_a_value() {
+        tokenize("` 1");
+    }
+
+    #[test]
+    #[should_panic(expected = "only ops can be quoted, got nothing")]
+    fn quoting_nothing() {
+        tokenize("1 `");
+    }
+
+    #[test]
+    fn gcd_and_lcm() {
+        assert_eq!(run("12 18 gcd 4 6 lcm").1, ints(&[6, 12]));
+        assert_eq!(run("0 5 gcd 0 5 lcm").1, ints(&[5, 0]));
+        // negative inputs still give a positive result
+        assert_eq!(run("0 12 - 18 gcd 0 4 - 6 lcm 4 0 6 - lcm").1, ints(&[6, 12, 12]));
+        assert_eq!(run("0 2147483647 - 1 - 2 gcd").1, ints(&[2]));
+    }
+
+    #[test]
+    #[should_panic(expected = "gcd of 0 and 0 isnt defined")]
+    fn gcd_of_zeros() {
+        run("0 0 gcd");
+    }
+
+    #[test]
+    #[should_panic(expected = "lcm of 2147483647 and 2147483646 doesnt fit in an int")]
+    fn lcm_overflow() {
+        run("2147483647 2147483646 lcm");
+    }
+
+    #[test]
+    fn modpow() {
+        assert_eq!(run("4 13 497 modpow").1, ints(&[445]));
+        assert_eq!(run("5 3 1 modpow 5 0 1 modpow 5 0 7 modpow").1, ints(&[0, 0, 1]));
+        // the base can be negative, the result is still between 0 and the modulus
+        assert_eq!(run("0 2 - 3 5 modpow").1, ints(&[2]));
+        // squaring near i32::MAX would overflow without the wider intermediate
+        assert_eq!(run("2147483646 2147483646 2147483647 modpow").1, ints(&[1]));
+        assert_eq!(run("2 31 2147483647 modpow 2147483646 3 2147483646 modpow").1, ints(&[1, 0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "modpow needs a positive modulus")]
+    fn modpow_with_modulus_zero() {
+        run("2 3 0 modpow");
+    }
+
+    #[test]
+    fn deferred_blocks_run_last_first() {
+        let src = "{ 1 println } defer { 2 println } defer 3 println";
+        assert_eq!(printed(src), "3\n2\n1\n");
+    }
+
+    #[test]
+    fn defer_runs_once_per_pass() {
+        let src = "0 3 range i { { i println } defer \"pass\" println } for \"done\" println";
+        assert_eq!(printed(src), "pass\n0\npass\n1\npass\n2\ndone\n");
+        let src = "n let 0 = { { \"end\" println } defer n n 1 + = n 2 == { break } if } loop";
+        assert_eq!(printed(src), "end\nend\n");
+    }
+
+    #[test]
+    fn defer_runs_on_break() {
+        let src = "{ { \"cleanup\" println } defer break \"never\" println } loop \"after\" println";
+        assert_eq!(printed(src), "cleanup\nafter\n");
+    }
+
+    #[test]
+    fn exit_in_a_deferred_block_wins() {
+        let (out, _, flow) = run("{ 3 exit } defer { \"first\" println } defer \"body\" println");
+        assert_eq!(out, "body\nfirst\n");
+        assert_eq!(flow, Flow::Exit(3));
+        // even over an exit from the body itself
+        assert_eq!(run("{ 4 exit } defer 1 exit").2, Flow::Exit(4));
+    }
+
+    #[test]
+    fn contains_in_an_array() {
+        assert_eq!(run("[ 1 2 ] 1 contains [ 1 2 ] 3 contains").1, ints(&[1, 0]));
+        assert_eq!(run("[ ( 1 \"a\" ) [ 2 ] ] ( 1 \"a\" ) contains [ [ 2 ] ] [ 2 ] contains").1, ints(&[1, 1]));
+        assert_eq!(run("0 5 range 4 contains [ \"ab\" ] \"a\" contains").1, ints(&[1, 0]));
+        assert_eq!(run("\"hello\" \"ell\" contains \"hello\" \"z\" contains").1, ints(&[1, 0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "starts_with needs a string to look in")]
+    fn starts_with_on_an_array() {
+        run("[ 1 2 ] 1 starts_with");
+    }
+
+    #[test]
+    fn exit_stops_nested_scopes() {
+        let src = "3 { 0 4 range i { i 2 == { 7 exit } if } for } times \"after\" println";
+        assert_eq!(run(src), (String::new(), vec![], Flow::Exit(7)));
+    }
+
+    #[test]
+    fn exit_from_a_fn() {
+        let src = "f let ( ) { \"in\" println 3 exit } fn = f @ \"after\" println";
+        assert_eq!(run(src), ("in\n".to_string(), vec![], Flow::Exit(3)));
+    }
+
+    #[test]
+    fn exit_inside_callbacks() {
+        for src in [
+            "[ 1 2 3 ] ( x ) { 7 exit } fn any \"after\" println",
+            "[ 1 2 3 ] ( x ) { 7 exit } fn all \"after\" println",
+            "[ 1 2 3 ] ( x ) { 7 exit } fn partition \"after\" println",
+            "[ 1 2 3 ] ( x ) { 7 exit } fn min_by \"after\" println",
+            "[ 1 2 3 ] ( x ) { 7 exit } fn max_by \"after\" println",
+            "f let ( a x ) { 7 exit } fn = [ 1 2 ] 0 f scan \"after\" println",
+        ] {
+            assert_eq!(run(src), (String::new(), vec![], Flow::Exit(7)), "{}", src);
+        }
+    }
+
+    #[test]
+    fn min_by_key() {
+        let src = "[ \"ab\" \"abcd\" \"a\" ] ( s ) { s count } fn max_by println";
+        assert_eq!(printed(src), "abcd\n");
+        assert_eq!(run("[ ] ( s ) { s } fn min_by").1, vec![Value::None]);
+    }
+
+    #[test]
+    fn exit_inside_literals() {
+        for src in [
+            "[ 1 7 exit 3 ] println \"after\" println",
+            "( 1 7 exit ) println \"after\" println",
+            "( 1 7 exit ) 3 pad_left \"after\" println",
+            "( a b ) let ( 1 7 exit ) = \"after\" println",
+            "( 7 exit ) 1 print_n \"after\" println",
+        ] {
+            assert_eq!(run(src).2, Flow::Exit(7), "{}", src);
+            assert_eq!(printed(src), "", "{}", src);
+        }
     }
-    vals
 }
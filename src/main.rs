@@ -1,6 +1,6 @@
 use std::fs;
 use std::collections::hash_map;
-use std::fmt::Display;
+use knusper::Flow;
 use knusper::InterpreterState;
 use knusper::Value;
 use knusper::tokenize;
@@ -11,19 +11,15 @@ fn main() {
         let fortnite = fs::read_to_string(file).unwrap();
         // println!("Hello, world! {:?}", vals);
         let mut ext_fns: hash_map::HashMap<String, fn(Value) -> Value> = hash_map::HashMap::new();
-        ext_fns.insert("joe".to_string(), | a: Value | {
+        ext_fns.insert("joe".to_string(), | _a: Value | {
             println!("the joe biden among us drip shirt");
             Value::None
         });
-        let mut istate = InterpreterState {
-            stack: vec![],
-            vars: hash_map::HashMap::new(),
-            globals: hash_map::HashMap::new(),
-            delims: Vec::new(),
-            ext_fns: &ext_fns,
-        };
+        let mut istate = InterpreterState::new(&ext_fns);
         let vals = tokenize(&fortnite);
-        istate.run(&vals);
+        if let Flow::Exit(code) = istate.run(&vals) {
+            std::process::exit(code);
+        }
         // println!("{:?}, {:?}", istate.stack, istate.vars);
     //} else {
     //    println!("argument required");
@@ -0,0 +1,284 @@
+//! an opt-in infix/call syntax that lowers to the normal stack tokens,
+//! so `print(1 + 2)` becomes `1 2 + print` and runs on the same interpreter
+
+use std::fmt::Display;
+
+use crate::{Keyword, Op, Value, WORDS};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    /// char offset into the source
+    pub pos: usize,
+    pub msg: String,
+}
+
+impl Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} at {}", self.msg, self.pos)
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Int(i32),
+    Str(String),
+    Name(String),
+    Sym(&'static str),
+    /// newlines and ; both end a statement
+    End,
+}
+
+const SYMS: &[&str] = &["==", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "=", "(", ")", ","];
+
+fn lex(src: &str) -> Result<Vec<(usize, Tok)>, SyntaxError> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let start = i;
+        if ch == '\n' || ch == ';' {
+            toks.push((start, Tok::End));
+            i += 1;
+        } else if ch.is_whitespace() {
+            i += 1;
+        } else if ch.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let s: String = chars[start..i].iter().collect();
+            let n = s.parse().map_err(|_| SyntaxError { pos: start, msg: format!("{} is too big", s) })?;
+            toks.push((start, Tok::Int(n)));
+        } else if ch.is_alphabetic() || ch == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            toks.push((start, Tok::Name(chars[start..i].iter().collect())));
+        } else if ch == '"' {
+            let mut s = String::new();
+            i += 1;
+            loop {
+                match chars.get(i) {
+                    None => return Err(SyntaxError { pos: start, msg: "unclosed string".to_string() }),
+                    Some('"') => break,
+                    // same escapes as the stack syntax
+                    Some('\\') => {
+                        i += 1;
+                        s.push(match chars.get(i) {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some('r') => '\r',
+                            Some('0') => '\0',
+                            Some('\\') => '\\',
+                            Some('"') => '"',
+                            _ => return Err(SyntaxError { pos: i, msg: "unknown escape".to_string() }),
+                        });
+                    }
+                    Some(c) => s.push(*c),
+                }
+                i += 1;
+            }
+            i += 1;
+            toks.push((start, Tok::Str(s)));
+        } else if let Some(sym) = SYMS.iter().find(|s| {
+            // the longest symbol is 2 chars, and they come before their 1 char prefixes in SYMS
+            let next: String = chars[i..].iter().take(2).collect();
+            next.starts_with(**s)
+        }) {
+            toks.push((start, Tok::Sym(sym)));
+            i += sym.len();
+        } else {
+            return Err(SyntaxError { pos: start, msg: format!("unexpected {:?}", ch) });
+        }
+    }
+    Ok(toks)
+}
+
+struct Parser {
+    toks: Vec<(usize, Tok)>,
+    at: usize,
+    end_pos: usize,
+    out: Vec<Value>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Tok> {
+        self.toks.get(self.at).map(|(_, t)| t)
+    }
+    fn pos(&self) -> usize {
+        self.toks.get(self.at).map(|(p, _)| *p).unwrap_or(self.end_pos)
+    }
+    fn err<T>(&self, msg: &str) -> Result<T, SyntaxError> {
+        Err(SyntaxError { pos: self.pos(), msg: msg.to_string() })
+    }
+    fn eat(&mut self, sym: &str) -> bool {
+        if matches!(self.peek(), Some(Tok::Sym(s)) if *s == sym) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, sym: &str) -> Result<(), SyntaxError> {
+        if self.eat(sym) {
+            Ok(())
+        } else {
+            self.err(&format!("expected {}", sym))
+        }
+    }
+    fn statement(&mut self) -> Result<(), SyntaxError> {
+        // let x = e  ->  x let e =
+        if let Some(Tok::Name(n)) = self.peek() {
+            if n == "let" {
+                self.at += 1;
+                let Some(Tok::Name(name)) = self.peek().cloned() else {
+                    return self.err("expected a name after let");
+                };
+                self.at += 1;
+                self.out.push(Value::Ident(name));
+                self.out.push(Value::Keyword(Keyword::Let));
+                self.expect("=")?;
+                self.expr()?;
+                self.out.push(Value::Operation(Op::Assign));
+                return Ok(());
+            }
+            // x = e  ->  x e =
+            if let Some((_, Tok::Sym("="))) = self.toks.get(self.at + 1) {
+                self.out.push(Value::Ident(n.clone()));
+                self.at += 2;
+                self.expr()?;
+                self.out.push(Value::Operation(Op::Assign));
+                return Ok(());
+            }
+        }
+        self.expr()
+    }
+    fn expr(&mut self) -> Result<(), SyntaxError> {
+        self.sum()?;
+        loop {
+            let op = if self.eat("==") {
+                Op::Equal
+            } else if self.eat("<=") {
+                Op::LessEq
+            } else if self.eat(">=") {
+                Op::GreaterEq
+            } else if self.eat("<") {
+                Op::Less
+            } else if self.eat(">") {
+                Op::Greater
+            } else {
+                return Ok(());
+            };
+            self.sum()?;
+            self.out.push(Value::Operation(op));
+        }
+    }
+    fn sum(&mut self) -> Result<(), SyntaxError> {
+        self.term()?;
+        loop {
+            let op = if self.eat("+") {
+                Op::Add
+            } else if self.eat("-") {
+                Op::Sub
+            } else {
+                return Ok(());
+            };
+            self.term()?;
+            self.out.push(Value::Operation(op));
+        }
+    }
+    fn term(&mut self) -> Result<(), SyntaxError> {
+        self.unary()?;
+        loop {
+            let op = if self.eat("*") {
+                Op::Mul
+            } else if self.eat("/") {
+                Op::Div
+            } else if self.eat("%") {
+                Op::Mod
+            } else {
+                return Ok(());
+            };
+            self.unary()?;
+            self.out.push(Value::Operation(op));
+        }
+    }
+    fn unary(&mut self) -> Result<(), SyntaxError> {
+        // there are no negative literals, so -x is 0 x -
+        if self.eat("-") {
+            self.out.push(Value::Int(0));
+            self.unary()?;
+            self.out.push(Value::Operation(Op::Sub));
+            return Ok(());
+        }
+        self.atom()
+    }
+    fn atom(&mut self) -> Result<(), SyntaxError> {
+        match self.peek().cloned() {
+            Some(Tok::Int(i)) => {
+                self.at += 1;
+                self.out.push(Value::Int(i));
+            }
+            Some(Tok::Str(s)) => {
+                self.at += 1;
+                self.out.push(Value::String(s));
+            }
+            Some(Tok::Name(name)) => {
+                self.at += 1;
+                let word = WORDS.iter().find(|(w, _)| *w == name).map(|(_, v)| v.clone());
+                if self.eat("(") {
+                    // args go on the stack first, then the word or fn that uses them
+                    if !self.eat(")") {
+                        loop {
+                            self.expr()?;
+                            if self.eat(")") {
+                                break;
+                            }
+                            self.expect(",")?;
+                        }
+                    }
+                    // call form always means the word, a var with the same name doesnt hide it here
+                    match word {
+                        Some(w) => self.out.push(w),
+                        None => {
+                            self.out.push(Value::Ident(name));
+                            self.out.push(Value::Operation(Op::CallFn));
+                        }
+                    }
+                } else {
+                    // word ops stay idents like in the stack syntax, so a var can shadow them
+                    match word {
+                        Some(kw @ Value::Keyword(_)) => self.out.push(kw),
+                        _ => self.out.push(Value::Ident(name)),
+                    }
+                }
+            }
+            Some(Tok::Sym("(")) => {
+                self.at += 1;
+                self.expr()?;
+                self.expect(")")?;
+            }
+            _ => return self.err("expected a value"),
+        }
+        Ok(())
+    }
+}
+
+/// turns the infix syntax into the same values `tokenize` gives, ready for `Program::from_values`
+pub fn lower(src: &str) -> Result<Vec<Value>, SyntaxError> {
+    let mut p = Parser { toks: lex(src)?, at: 0, end_pos: src.chars().count(), out: vec![] };
+    while p.peek().is_some() {
+        if p.peek() == Some(&Tok::End) {
+            p.at += 1;
+            continue;
+        }
+        p.statement()?;
+        match p.peek() {
+            None | Some(Tok::End) => {}
+            _ => return p.err("expected the end of the statement"),
+        }
+    }
+    Ok(p.out)
+}